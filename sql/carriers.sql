@@ -0,0 +1,42 @@
+-- Fleet carriers and the systems they were seen in, queried by the carrier, commodity, market and trade endpoints.
+
+-- Fleet carriers are listed as stations named after their callsign, e.g. `K7N-T2X`.
+alter table station add column if not exists carrier boolean
+    generated always as (coalesce(name ~ '^[A-Z0-9]{3}-[A-Z0-9]{3}$', false)) stored;
+create index if not exists station_carrier_name_idx on station (name) where carrier;
+
+-- Every system a carrier was seen in, with the unix timestamp it was first seen there.
+create table if not exists carrier_location
+(
+    id          bigserial primary key,
+    market_id   bigint not null,
+    system_name text   not null,
+    arrived     bigint not null
+);
+create index if not exists carrier_location_market_idx on carrier_location (market_id, arrived, id);
+
+create or replace function record_carrier_location() returns trigger as
+$$
+begin
+    if new.carrier and new.system_name is not null
+        and (tg_op = 'INSERT' or new.system_name is distinct from old.system_name) then
+        insert into carrier_location (market_id, system_name, arrived)
+        values (new.market_id, new.system_name, extract(epoch from now())::bigint);
+    end if;
+    return new;
+end;
+$$ language plpgsql;
+
+drop trigger if exists station_carrier_location on station;
+create trigger station_carrier_location
+    after insert or update of system_name on station
+    for each row
+execute function record_carrier_location();
+
+-- Current location of carriers known before the trigger existed
+insert into carrier_location (market_id, system_name, arrived)
+select s.market_id, s.system_name, extract(epoch from now())::bigint
+from station s
+where s.carrier
+  and s.system_name is not null
+  and not exists (select 1 from carrier_location l where l.market_id = s.market_id);
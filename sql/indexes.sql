@@ -0,0 +1,33 @@
+-- Indexes the api relies on to avoid full table scans.
+
+-- System lookup by name and name autocomplete
+create index if not exists system_lower_name_idx on system (odyssey, lower(name) text_pattern_ops);
+
+-- Spatial queries (nearby systems, trade routes, nearest markets, body search, route plotting) filter on a box first.
+-- The expression has to match systems::POSITION exactly for postgres to use the index.
+create extension if not exists cube;
+create extension if not exists btree_gist;
+create index if not exists system_position_idx on system using gist (odyssey, cube(array[x, y, z]::float8[]));
+drop index if exists system_x_idx;
+drop index if exists system_y_idx;
+drop index if exists system_z_idx;
+
+-- Stations of a system
+create index if not exists station_system_name_idx on station (system_name);
+
+-- Market listing of a commodity
+create index if not exists commodity_name_idx on commodity (name, odyssey);
+
+-- Commodity history ranges and candles
+create index if not exists commodity_history_name_idx on commodity_history (name, odyssey, timestamp);
+
+-- Price movers over a recent window across all commodities
+create index if not exists commodity_history_timestamp_idx on commodity_history (odyssey, timestamp);
+
+-- Body search by class or star type, paged in the order of system address and body id
+create index if not exists body_page_idx on body (odyssey, system_address, id);
+create index if not exists star_page_idx on star (odyssey, system_address, id);
+create index if not exists body_class_page_idx on body (odyssey, lower(class), system_address, id);
+create index if not exists star_type_page_idx on star (odyssey, lower(type), system_address, id);
+drop index if exists body_class_idx;
+drop index if exists star_type_idx;
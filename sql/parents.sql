@@ -0,0 +1,10 @@
+-- Order of the parents of a body, queried by every endpoint returning bodies.
+
+-- The journal lists the parents of a body nearest first and they are inserted in that order.
+-- Rows which exist already are numbered in the order they are stored, which is their insertion order
+-- as long as the table was only ever inserted into.
+alter table parent add column if not exists id bigserial;
+
+-- Parents of a system or of the bodies in search results, in order
+create index if not exists parent_body_order_idx on parent (system_address, body_id, id);
+drop index if exists parent_body_idx;
@@ -1,9 +1,27 @@
+mod body;
+mod cache;
+mod catalogue;
+mod dlc;
+mod error;
+mod history;
+mod market;
+mod orbit;
+mod parent;
+mod route;
+mod station;
+mod systems;
+mod trade;
+mod tree;
+mod valuation;
+
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
 use rocket::fairing::AdHoc;
-use rocket::futures::Sink;
-use rocket::State;
+use rocket::form;
+use rocket::{Build, Orbit, Request, Rocket, State};
+use rocket::request::{FromRequest, Outcome};
 use rocket::serde::{Serialize, Deserialize, json::Json};
 
 use rocket_sync_db_pools::database;
@@ -12,100 +30,78 @@ use rocket_sync_db_pools::database;
 use rocket_sync_db_pools::postgres;
 use serde_json::{json, Value};
 
-const CACHE_TIMEOUT: u64 = 600;
+use body::BodyDetail;
+use cache::{CacheConfig, SharedCache};
+use catalogue::{CatalogueEntry, CommodityName};
+use dlc::Dlc;
+use error::{query_param, ApiError};
+use history::{CandleKey, CommodityCandles, Mover};
+use parent::{Parent, ParentFormat};
+use route::RouteConfig;
+use station::Station;
+use valuation::ScanValue;
 
 #[database("postgres_db")]
 struct DbConn(postgres::Client);
 
-struct Cache {
-    commodity_history: HashMap<String, CommodityHistoryCache>,
-    commodity: HashMap<String, CommodityCache>,
-    system: HashMap<i64, SystemCache>,
-}
+/// Request guard handing out a [`DbConn`] only when it is needed, so cached responses
+/// and requests waiting on a running load do not hold a pooled connection.
+struct LazyDbConn<'r>(&'r Rocket<Orbit>);
 
-impl Cache {
-    fn get_commodity(&self, name: String) -> Option<Commodity> {
-        let result = self.commodity.get(name.as_str());
-        return match result {
-            None => {
-                //Not cached yet
-                None
-            }
-            Some(commodity) => {
-                //10 minutes
-                if commodity.instant.elapsed().as_secs() > CACHE_TIMEOUT {
-                    //Cache too old -> sending nothing
-                    None
-                } else {
-                    //Cache usable -> sending
-                    Some(commodity.data.clone())
-                }
-            }
-        };
+impl LazyDbConn<'_> {
+    async fn get(&self) -> Result<DbConn, ApiError> {
+        DbConn::get_one(self.0).await
+            .ok_or_else(|| ApiError::Database("No database connection available".to_string()))
     }
+}
 
-    fn put_commodity(&mut self, commodity: Commodity, name: String) {
-        let commodity_cache = CommodityCache {
-            instant: Instant::now(),
-            data: commodity,
-        };
-        self.commodity.insert(name, commodity_cache);
-    }
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LazyDbConn<'r> {
+    type Error = Infallible;
 
-    fn get_commodity_history(&self, name: String) -> Option<CommodityHistory> {
-        let result = self.commodity_history.get(name.as_str());
-        return match result {
-            None => {
-                //Not cached yet
-                None
-            }
-            Some(commodity_history) => {
-                //10 minutes
-                if commodity_history.instant.elapsed().as_secs() > CACHE_TIMEOUT {
-                    //Cache too old -> sending nothing
-                    None
-                } else {
-                    //Cache usable -> sending
-                    Some(commodity_history.data.clone())
-                }
-            }
-        };
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(LazyDbConn(request.rocket()))
     }
+}
 
-    fn put_commodity_history(&mut self, commodity_history: CommodityHistory, name: String) {
-        let commodity_cache = CommodityHistoryCache {
-            instant: Instant::now(),
-            data: commodity_history,
-        };
-        self.commodity_history.insert(name, commodity_cache);
-    }
+/// Cache key made up of the dlc flag (odyssey = true) and the id of the cached resource.
+/// Odyssey and Horizons data live side by side in the database, so the flag must be part of the key.
+type CacheKey<T> = (bool, T);
 
-    fn get_system(&self, address: i64) -> Option<System> {
-        let result = self.system.get(&address);
-        return match result {
-            None => {
-                //Not cached yet
-                None
-            }
-            Some(system) => {
-                //10 minutes
-                if system.instant.elapsed().as_secs() > CACHE_TIMEOUT {
-                    //Cache too old -> sending nothing
-                    None
-                } else {
-                    //Cache usable -> sending
-                    Some(system.data.clone())
-                }
-            }
-        };
+struct Cache {
+    commodity_history: SharedCache<CacheKey<String>, CommodityHistory, ApiError>,
+    commodity_candles: SharedCache<CacheKey<CandleKey>, CommodityCandles, ApiError>,
+    commodity_movers: SharedCache<CacheKey<i64>, Vec<Mover>, ApiError>,
+    commodities: SharedCache<CacheKey<()>, Vec<CatalogueEntry>, ApiError>,
+    commodity: SharedCache<CacheKey<CommodityKey>, Commodity, ApiError>,
+    system: SharedCache<CacheKey<i64>, System, ApiError>,
+    /// Lower case system name to address
+    system_address: SharedCache<CacheKey<String>, i64, ApiError>,
+    body: SharedCache<CacheKey<(i64, i32)>, BodyDetail, ApiError>,
+    station: SharedCache<CacheKey<i64>, Station, ApiError>,
+    system_stations: SharedCache<CacheKey<i64>, Vec<Station>, ApiError>,
+}
+
+impl Cache {
+    fn new(config: &CacheConfig) -> Self {
+        Cache {
+            commodity_history: SharedCache::new(config.commodity_history),
+            commodity_candles: SharedCache::new(config.commodity_candles),
+            commodity_movers: SharedCache::new(config.commodity_movers),
+            commodities: SharedCache::new(config.commodities),
+            commodity: SharedCache::new(config.commodity),
+            system: SharedCache::new(config.system),
+            system_address: SharedCache::new(config.system_address),
+            body: SharedCache::new(config.body),
+            station: SharedCache::new(config.station),
+            system_stations: SharedCache::new(config.system_stations),
+        }
     }
 
-    fn put_system(&mut self, system: System, address: i64) {
-        let system_cache = SystemCache {
-            instant: Instant::now(),
-            data: system,
-        };
-        self.system.insert(address, system_cache);
+    fn evict_expired(&self) -> usize {
+        self.commodity_history.evict_expired() + self.commodity_candles.evict_expired() + self.commodity_movers.evict_expired()
+            + self.commodities.evict_expired() + self.commodity.evict_expired() + self.system.evict_expired()
+            + self.system_address.evict_expired() + self.body.evict_expired() + self.station.evict_expired() + self.system_stations.evict_expired()
     }
 }
 
@@ -114,14 +110,65 @@ async fn root() -> &'static str {
     "data"
 }
 
+/// One page of a list endpoint. `next_offset` is only set if there are more results.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Page<T> {
+    results: Vec<T>,
+    limit: i64,
+    offset: i64,
+    next_offset: Option<i64>,
+}
+
+const DEFAULT_PAGE_LIMIT: i64 = 20;
+const MAX_PAGE_LIMIT: i64 = 100;
+
+/// Validates the paging parameters of a list endpoint and fills in the defaults.
+fn page_bounds(limit: Option<i64>, offset: Option<i64>) -> Result<(i64, i64), ApiError> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+    let offset = offset.unwrap_or(0);
+    if !(1..=MAX_PAGE_LIMIT).contains(&limit) {
+        return Err(ApiError::BadRequest(format!("Parameter limit must be between 1 and {}", MAX_PAGE_LIMIT)));
+    }
+    if offset < 0 {
+        return Err(ApiError::BadRequest("Parameter offset must not be negative".to_string()));
+    }
+    Ok((limit, offset))
+}
+
+impl<T> Page<T> {
+    /// Builds a page from up to `limit + 1` results, the extra one only signals a next page.
+    fn new(mut results: Vec<T>, limit: i64, offset: i64) -> Self {
+        let has_more = results.len() as i64 > limit;
+        results.truncate(limit as usize);
+        Page {
+            results,
+            limit,
+            offset,
+            next_offset: has_more.then_some(offset + limit),
+        }
+    }
+}
+
+#[get("/cache")]
+async fn cache_stats(cache: &State<Arc<Cache>>) -> Json<Value> {
+    Json(json!({
+        "system": cache.system.stats(),
+        "system_address": cache.system_address.stats(),
+        "body": cache.body.stats(),
+        "commodity": cache.commodity.stats(),
+        "commodity_history": cache.commodity_history.stats(),
+        "commodity_candles": cache.commodity_candles.stats(),
+        "commodity_movers": cache.commodity_movers.stats(),
+        "commodities": cache.commodities.stats(),
+        "station": cache.station.stats(),
+        "system_stations": cache.system_stations.stats(),
+    }))
+}
+
 /**
  * System
  **/
-struct SystemCache {
-    instant: Instant,
-    data: System,
-}
-
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
 struct System {
@@ -172,7 +219,10 @@ pub struct Planet {
     pub axial_tilt: Option<f32>,
     pub was_discovered: Option<bool>,
     pub was_mapped: Option<bool>,
-    pub parents: Vec<Value>,
+    /// Nearest parent first
+    pub parents: Vec<Parent>,
+    /// Estimated payout for the cartographic data
+    pub estimated_value: Option<ScanValue>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -200,188 +250,224 @@ pub struct Star {
     pub axial_tilt: Option<f32>,
     pub was_discovered: Option<bool>,
     pub was_mapped: Option<bool>,
-    pub parents: Vec<Value>,
+    /// Nearest parent first
+    pub parents: Vec<Parent>,
+    /// Estimated payout for the cartographic data
+    pub estimated_value: Option<ScanValue>,
 }
 
-#[get("/<dlc>/system/<address>")]
-async fn system(cache: &State<Arc<Mutex<Cache>>>, db: DbConn, address: i64, dlc: String) -> Option<Json<System>> {
-    let odyssey = dlc.contains("odyssey");
-    let some_system = cache.lock().unwrap().get_system(address);
-    return match some_system {
-        None => {
-            let system: Option<System> = db.run(move |conn| {
-
-                //language=postgresql
-                let row_option = conn.query_one("select name,address,body_count,non_body_count,population,allegiance,economy,second_economy,government,security,faction,x,y,z from system where address = $1 and odyssey = $2",
-                                                &[&address, &odyssey]).ok();
-
-                if let Some(row) = row_option {
-                    let mut local_system = System {
-                        name: row.get(0),
-                        address: Option::from(address),
-                        body_count: row.get(2),
-                        non_body_count: row.get(3),
-                        population: row.get(4),
-                        allegiance: row.get(5),
-                        economy: row.get(6),
-                        second_economy: row.get(7),
-                        government: row.get(8),
-                        security: row.get(9),
-                        faction: row.get(10),
-                        x: row.get(11),
-                        y: row.get(12),
-                        z: row.get(13),
-                        planets: None,
-                        stars: None,
-                    };
-
-                    //language=postgresql
-                    let mut sql = "select name,id,distance_from_arrival_ls,type,subclass,stellar_mass,
-                        radius,absolute_magnitude,age_my,surface_temperature,luminosity,semi_major_axis,eccentricity,
-                        orbital_inclination,periapsis,orbital_period,ascending_node,mean_anomaly,rotation_period,
-                        axial_tilt,discovered,mapped from star where system_address = $1 and odyssey = $2";
-
-                    let stars_option = conn.query(sql, &[&address, &odyssey]).ok();
-
-                    if let Some(stars) = stars_option {
-                        let mut star_vec: Vec<Star> = vec![];
-
-                        for r in stars {
-
-                            let id : i32 = r.get(1);
-                            //language=postgresql
-                            let parents_sql = "select parent_type,parent_id from parent where system_address = $1 and body_id = $2";
-
-                            let parents_option = conn.query(parents_sql,&[&address,&id]).unwrap();
-
-                            let mut parent_array:Vec<Value> = vec![];
-                            for row in parents_option {
-                                let key: &str = row.get(0);
-                                let value: i32 = row.get(1);
-                                let json_object = json!({ key: value });
-                                parent_array.push(json_object);
-                            }
-
-
-
-                            let discovered = r.get(20);
-                            let mapped = r.get(21);
-                            star_vec.push(Star {
-                                body_name: r.get(0),
-                                body_id: Some(id),
-                                distance_from_arrival_ls: Some(r.get(2)),
-                                star_type: r.get(3),
-                                subclass: r.get(4),
-                                stellar_mass: r.get(5),
-                                radius: r.get(6),
-                                absolute_magnitude: r.get(7),
-                                age_my: r.get(8),
-                                surface_temperature: r.get(9),
-                                luminosity: r.get(10),
-                                semi_major_axis: r.get(11),
-                                eccentricity: r.get(12),
-                                orbital_inclination: r.get(13),
-                                periapsis: r.get(14),
-                                orbital_period: r.get(15),
-                                ascending_node: r.get(16),
-                                mean_anomaly: r.get(17),
-                                rotation_period: r.get(18),
-                                axial_tilt: r.get(19),
-                                was_discovered: discovered,
-                                was_mapped: mapped,
-                                parents: parent_array,
-                            });
-                        }
-
-                        local_system.stars = Some(star_vec);
-                    }
+/// A system with all of its stars and planets. `parents=journal` returns the parents in the journal format.
+#[get("/<dlc>/system/<address>?<parents>")]
+async fn system(cache: &State<Arc<Cache>>, db: LazyDbConn<'_>, address: i64, dlc: Result<Dlc, ApiError>, parents: form::Result<'_, ParentFormat>) -> Result<Json<Value>, ApiError> {
+    let odyssey = dlc?.odyssey();
+    let parents = query_param("parents", parents)?.unwrap_or_default();
+    let system = cached_system(cache, &db, odyssey, address).await?;
+    parents.apply(&system).map(Json)
+}
 
-                    //language=postgresql
-                    sql = "select name,id,distance_from_arrival_ls,tidal_lock,terraform_state,class,atmosphere,volcanism,mass_em,radius,surface_gravity,surface_temperature,surface_pressure,
-                    landable,semi_major_axis,eccentricity,orbital_inclination,periapsis,orbital_period,ascending_node,mean_anomaly,rotation_period,axial_tilt,discovered,mapped from body where system_address = $1 and odyssey = $2";
-
-                    let planets_options = conn.query(sql, &[&address, &odyssey]).ok();
-
-
-                    if let Some(planets) = planets_options {
-                        let mut planet_vec: Vec<Planet> = vec![];
-
-                        for r in planets {
-
-                            let id : i32 = r.get(1);
-                            //language=postgresql
-                            let parents_sql = "select parent_type,parent_id from parent where system_address = $1 and body_id = $2";
-
-                            let parents_option = conn.query(parents_sql,&[&address,&id]).unwrap();
-
-                            let mut parent_array:Vec<Value> = vec![];
-                            for row in parents_option {
-                                let key: &str = row.get(0);
-                                let value: i32 = row.get(1);
-                                let json_object = json!({ key: value });
-                                parent_array.push(json_object);
-                            }
-
-                            let tidal_lock = r.get(3);
-                            let discovered: Option<bool> = r.get(23);
-                            let mapped: Option<bool> = r.get(24);
-
-                            planet_vec.push(Planet {
-                                body_name: r.get(0),
-                                body_id: Some(id),
-                                distance_from_arrival_ls: r.get(2),
-                                tidal_lock,
-                                terraform_state: r.get(4),
-                                planet_class: r.get(5),
-                                atmosphere: r.get(6),
-                                volcanism: r.get(7),
-                                mass_em: r.get(8),
-                                radius: r.get(9),
-                                surface_gravity: r.get(10),
-                                surface_temperature: r.get(11),
-                                surface_pressure: r.get(12),
-                                landable: r.get(13),
-                                semi_major_axis: r.get(14),
-                                eccentricity: r.get(15),
-                                orbital_inclination: r.get(16),
-                                periapsis: r.get(17),
-                                orbital_period: r.get(18),
-                                ascending_node: r.get(19),
-                                mean_anomaly: r.get(20),
-                                rotation_period: r.get(21),
-                                axial_tilt: r.get(22),
-                                was_discovered: discovered,
-                                was_mapped: mapped,
-                                parents: parent_array,
-                            });
-                        }
-                        local_system.planets = Some(planet_vec);
-                    }
-                    return Some(local_system);
-                }
-                None
-            }).await;
-
-            //Check if value is there. If not, do not cache! May lead to let memory bloat if there are too many wrong api calls
-            if let Some(system) = system {
-                let local_system = system.clone();
-                cache.lock().unwrap().put_system(local_system.clone(), local_system.address.unwrap());
-                return Some(Json(local_system));
-            }
-            return None;
-        }
-        Some(system) => { Some(Json(system)) }
+/// Case insensitive exact match on the system name. Resolves the address and answers like [`system`].
+#[get("/<dlc>/system/by-name/<name>?<parents>", rank = 2)]
+async fn system_by_name(cache: &State<Arc<Cache>>, db: LazyDbConn<'_>, name: &str, dlc: Result<Dlc, ApiError>, parents: form::Result<'_, ParentFormat>) -> Result<Json<Value>, ApiError> {
+    let odyssey = dlc?.odyssey();
+    let parents = query_param("parents", parents)?.unwrap_or_default();
+    let name = name.to_lowercase();
+    let db = &db;
+    let address = cache.system_address.get_or_load((odyssey, name.clone()), || async move {
+        let conn = db.get().await?;
+        conn.run(move |conn| {
+            //language=postgresql
+            let sql = "select address from system where odyssey = $1 and lower(name) = $2 order by address limit 1";
+            conn.query_opt(sql, &[&odyssey, &name])?
+                .ok_or_else(|| ApiError::NotFound(format!("System {} not found", name)))?
+                .try_get::<_, i64>(0)
+                .map_err(ApiError::from)
+        }).await
+    }).await?;
+
+    let system = cached_system(cache, db, odyssey, address).await?;
+    parents.apply(&system).map(Json)
+}
+
+/// A system from the cache, loaded with [`load_system`] on a miss.
+///
+/// Only found systems get cached. Caching misses may lead to memory bloat if there are too many wrong api calls
+async fn cached_system(cache: &Cache, db: &LazyDbConn<'_>, odyssey: bool, address: i64) -> Result<System, ApiError> {
+    cache.system.get_or_load((odyssey, address), || async move {
+        let conn = db.get().await?;
+        conn.run(move |conn| load_system(conn, address, odyssey)).await
+    }).await
+}
+
+/// Loads a system with all of its stars and planets.
+///
+/// Takes four queries no matter how many bodies the system has: the system itself, its stars,
+/// its planets and the parents of every body, which get assigned to the bodies afterwards.
+fn load_system(conn: &mut postgres::Client, address: i64, odyssey: bool) -> Result<System, ApiError> {
+    //language=postgresql
+    let row = conn.query_opt("select name,address,body_count,non_body_count,population,allegiance,economy,second_economy,government,security,faction,x,y,z from system where address = $1 and odyssey = $2",
+                             &[&address, &odyssey])?
+        .ok_or_else(|| ApiError::NotFound(format!("System {} not found", address)))?;
+
+    let mut system = System {
+        name: row.get(0),
+        address: Option::from(address),
+        body_count: row.get(2),
+        non_body_count: row.get(3),
+        population: row.get(4),
+        allegiance: row.get(5),
+        economy: row.get(6),
+        second_economy: row.get(7),
+        government: row.get(8),
+        security: row.get(9),
+        faction: row.get(10),
+        x: row.get(11),
+        y: row.get(12),
+        z: row.get(13),
+        planets: None,
+        stars: None,
     };
+
+    let mut parents = load_parents(conn, address)?;
+    system.stars = Some(load_stars(conn, address, odyssey, None, &mut parents)?);
+    system.planets = Some(load_planets(conn, address, odyssey, None, &mut parents)?);
+    Ok(system)
+}
+
+/// Columns read by [`star_from_row`], with the star table aliased as `st`.
+const STAR_COLUMNS: &str = "st.name,st.id,st.distance_from_arrival_ls,st.type,st.subclass,st.stellar_mass,
+    st.radius,st.absolute_magnitude,st.age_my,st.surface_temperature,st.luminosity,st.semi_major_axis,st.eccentricity,
+    st.orbital_inclination,st.periapsis,st.orbital_period,st.ascending_node,st.mean_anomaly,st.rotation_period,
+    st.axial_tilt,st.discovered,st.mapped";
+
+/// Columns read by [`planet_from_row`], with the body table aliased as `b`.
+const PLANET_COLUMNS: &str = "b.name,b.id,b.distance_from_arrival_ls,b.tidal_lock,b.terraform_state,b.class,b.atmosphere,b.volcanism,b.mass_em,b.radius,
+    b.surface_gravity,b.surface_temperature,b.surface_pressure,b.landable,b.semi_major_axis,b.eccentricity,b.orbital_inclination,b.periapsis,
+    b.orbital_period,b.ascending_node,b.mean_anomaly,b.rotation_period,b.axial_tilt,b.discovered,b.mapped";
+
+/// Stars of a system, or only the one with `body_id` if given. Their parents are taken out of `parents`.
+fn load_stars(conn: &mut postgres::Client, address: i64, odyssey: bool, body_id: Option<i32>, parents: &mut HashMap<i32, Vec<Parent>>) -> Result<Vec<Star>, ApiError> {
+    //language=postgresql
+    let sql = format!("select {STAR_COLUMNS} from star st where st.system_address = $1 and st.odyssey = $2 and ($3::int4 is null or st.id = $3)");
+
+    let stars = conn.query(sql.as_str(), &[&address, &odyssey, &body_id])?;
+    let mut star_vec: Vec<Star> = vec![];
+
+    for r in stars {
+        let id: i32 = r.get(1);
+        star_vec.push(star_from_row(&r, parents.remove(&id).unwrap_or_default()));
+    }
+    Ok(star_vec)
+}
+
+/// Reads a star from the first columns of `r`, selected with [`STAR_COLUMNS`].
+fn star_from_row(r: &postgres::Row, parents: Vec<Parent>) -> Star {
+    let discovered = r.get(20);
+    let mapped = r.get(21);
+    let mut star = Star {
+        body_name: r.get(0),
+        body_id: r.get(1),
+        distance_from_arrival_ls: r.get(2),
+        star_type: r.get(3),
+        subclass: r.get(4),
+        stellar_mass: r.get(5),
+        radius: r.get(6),
+        absolute_magnitude: r.get(7),
+        age_my: r.get(8),
+        surface_temperature: r.get(9),
+        luminosity: r.get(10),
+        semi_major_axis: r.get(11),
+        eccentricity: r.get(12),
+        orbital_inclination: r.get(13),
+        periapsis: r.get(14),
+        orbital_period: r.get(15),
+        ascending_node: r.get(16),
+        mean_anomaly: r.get(17),
+        rotation_period: r.get(18),
+        axial_tilt: r.get(19),
+        was_discovered: discovered,
+        was_mapped: mapped,
+        parents,
+        estimated_value: None,
+    };
+    star.estimated_value = valuation::star_value(star.star_type.as_deref(), star.stellar_mass, star.was_discovered);
+    star
+}
+
+/// Planets of a system, or only the one with `body_id` if given. Their parents are taken out of `parents`.
+fn load_planets(conn: &mut postgres::Client, address: i64, odyssey: bool, body_id: Option<i32>, parents: &mut HashMap<i32, Vec<Parent>>) -> Result<Vec<Planet>, ApiError> {
+    //language=postgresql
+    let sql = format!("select {PLANET_COLUMNS} from body b where b.system_address = $1 and b.odyssey = $2 and ($3::int4 is null or b.id = $3)");
+
+    let planets = conn.query(sql.as_str(), &[&address, &odyssey, &body_id])?;
+    let mut planet_vec: Vec<Planet> = vec![];
+
+    for r in planets {
+        let id: i32 = r.get(1);
+        planet_vec.push(planet_from_row(&r, parents.remove(&id).unwrap_or_default(), odyssey));
+    }
+    Ok(planet_vec)
+}
+
+/// Reads a planet from the first columns of `r`, selected with [`PLANET_COLUMNS`].
+fn planet_from_row(r: &postgres::Row, parents: Vec<Parent>, odyssey: bool) -> Planet {
+    let tidal_lock = r.get(3);
+    let discovered: Option<bool> = r.get(23);
+    let mapped: Option<bool> = r.get(24);
+
+    let mut planet = Planet {
+        body_name: r.get(0),
+        body_id: r.get(1),
+        distance_from_arrival_ls: r.get(2),
+        tidal_lock,
+        terraform_state: r.get(4),
+        planet_class: r.get(5),
+        atmosphere: r.get(6),
+        volcanism: r.get(7),
+        mass_em: r.get(8),
+        radius: r.get(9),
+        surface_gravity: r.get(10),
+        surface_temperature: r.get(11),
+        surface_pressure: r.get(12),
+        landable: r.get(13),
+        semi_major_axis: r.get(14),
+        eccentricity: r.get(15),
+        orbital_inclination: r.get(16),
+        periapsis: r.get(17),
+        orbital_period: r.get(18),
+        ascending_node: r.get(19),
+        mean_anomaly: r.get(20),
+        rotation_period: r.get(21),
+        axial_tilt: r.get(22),
+        was_discovered: discovered,
+        was_mapped: mapped,
+        parents,
+        estimated_value: None,
+    };
+    planet.estimated_value = valuation::planet_value(planet.planet_class.as_deref(), planet.mass_em, planet.terraform_state.as_deref(),
+                                                     planet.was_discovered, planet.was_mapped, odyssey);
+    planet
+}
+
+/// Parents of every body in the system, keyed by body id, nearest first in the insertion order of the table.
+fn load_parents(conn: &mut postgres::Client, address: i64) -> Result<HashMap<i32, Vec<Parent>>, ApiError> {
+    let mut parents: HashMap<i32, Vec<Parent>> = HashMap::new();
+
+    //language=postgresql
+    let parents_sql = "select body_id,parent_type,parent_id from parent where system_address = $1 order by id";
+
+    for row in conn.query(parents_sql, &[&address])? {
+        let (body_id, kind, parent_id): (i32, &str, i32) = (row.try_get(0)?, row.try_get(1)?, row.try_get(2)?);
+        match Parent::from_journal(kind, parent_id) {
+            Some(parent) => parents.entry(body_id).or_default().push(parent),
+            None => warn!("Skipping unknown parent type '{}' of body {} in system {}", kind, body_id, address),
+        }
+    }
+    Ok(parents)
 }
 
 /**
  * Commodity History
 **/
-struct CommodityHistoryCache {
-    instant: Instant,
-    data: CommodityHistory,
-}
-
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
 struct CommodityHistory {
@@ -394,172 +480,378 @@ struct CommodityHistory {
 /**
  * Commodity
  **/
-struct CommodityCache {
-    instant: Instant,
-    data: Commodity,
-}
-
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
 struct Commodity {
     #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
     name: Option<String>,
-    buy_price: i32,
-    sell_price: i32,
-    mean_price: i32,
+    /// Averages over every matching market, weighted by recency if a `half_life` is given
+    buy_price: Option<i32>,
+    sell_price: Option<i32>,
+    mean_price: Option<i32>,
+    /// Percentiles of the markets actually selling the commodity
+    buy_percentiles: Percentiles,
+    /// Percentiles of the markets actually buying the commodity
+    sell_percentiles: Percentiles,
+    /// Number of markets the statistics are based on
+    samples: i64,
+    /// Unix timestamps of the least and most recently updated market
+    oldest_update: Option<i64>,
+    newest_update: Option<i64>,
     lowest_buy_price: Value,
     highest_sell_price: Value,
 }
 
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Percentiles {
+    p10: Option<i32>,
+    median: Option<i32>,
+    p90: Option<i32>,
+}
+
+impl Percentiles {
+    fn from_column(values: Option<Vec<f64>>) -> Self {
+        match values.as_deref() {
+            Some([p10, median, p90]) => Percentiles {
+                p10: Some(p10.round() as i32),
+                median: Some(median.round() as i32),
+                p90: Some(p90.round() as i32),
+            },
+            _ => Percentiles::default(),
+        }
+    }
+}
+
+/// Commodity name, whether carriers are included, `max_age` and `half_life`
+type CommodityKey = (String, bool, Option<i64>, Option<i64>);
+
 #[get("/<dlc>/commodity_history/<name>")]
-async fn commodity_history(cache: &State<Arc<Mutex<Cache>>>, db: DbConn, name: String, dlc: String) -> Option<Json<CommodityHistory>> {
+async fn commodity_history(cache: &State<Arc<Cache>>, db: LazyDbConn<'_>, name: CommodityName, dlc: Result<Dlc, ApiError>) -> Result<Json<CommodityHistory>, ApiError> {
+    let name = name.0;
     let name_clone = name.clone();
-    let dlc_clone = dlc.contains("odyssey");
-    let some_commodity_history = cache.lock().unwrap().get_commodity_history(name.clone());
-    return match some_commodity_history {
-        None => {
-            let data: Option<CommodityHistory> = db.run(move |conn|{
-                //language=postgresql
-                let sql = "SELECT timestamp,buy_price,sell_price,mean_price FROM commodity_history where odyssey=$1 and name=$2 order by timestamp desc limit 1000";
-                let optional_rows = conn.query(sql,&[&dlc_clone,&name_clone]).ok();
-
-                if let Some(rows) = optional_rows {
-                    let mut price_array = json!([]);
-                    for row in rows{
-                        price_array.as_array_mut().unwrap().push(json!({
-                            "timestamp": row.get::<usize,i64>(0),
-                            "buy_price": row.get::<usize,i32>(1),
-                            "sell_price": row.get::<usize,i32>(2),
-                            "mean_price": row.get::<usize,i32>(3),
-                        }));
-                    }
-                    let commodity_history = CommodityHistory{
-                        name: Some(name_clone),
-                        odyssey: Some(dlc_clone),
-                        prices: price_array
-                    };
-                    return Some(commodity_history)
-                }
-                None
-            }).await;
+    let dlc_clone = dlc?.odyssey();
+    let commodity_history = cache.commodity_history.get_or_load((dlc_clone, name), || async move {
+        let db = db.get().await?;
+        db.run(move |conn| {
+            //language=postgresql
+            let sql = "SELECT timestamp,buy_price,sell_price,mean_price FROM commodity_history where odyssey=$1 and name=$2 order by timestamp desc limit 1000";
+            let rows = conn.query(sql, &[&dlc_clone, &name_clone])?;
+
+            let mut price_array = vec![];
+            for row in rows {
+                price_array.push(json!({
+                    "timestamp": row.try_get::<usize,i64>(0)?,
+                    "buy_price": row.try_get::<usize,i32>(1)?,
+                    "sell_price": row.try_get::<usize,i32>(2)?,
+                    "mean_price": row.try_get::<usize,i32>(3)?,
+                }));
+            }
+            Ok(CommodityHistory {
+                name: Some(name_clone),
+                odyssey: Some(dlc_clone),
+                prices: Value::Array(price_array),
+            })
+        }).await
+    }).await;
+    commodity_history.map(Json)
+}
 
-            if let Some(commodity_history) = data {
-                cache.lock().unwrap().put_commodity_history(commodity_history.clone(), name.clone());
-                return Some(Json(commodity_history));
+/// Price summary of a commodity.
+///
+/// `max_age` leaves out markets not updated within that many seconds. With `half_life` the averages
+/// are weighted by recency, a market updated `half_life` seconds ago counting half as much as a fresh one.
+#[get("/<dlc>/commodity/<name>?<include_carriers>&<max_age>&<half_life>")]
+async fn commodity(cache: &State<Arc<Cache>>, db: LazyDbConn<'_>, name: CommodityName, dlc: Result<Dlc, ApiError>, include_carriers: Option<bool>,
+                   max_age: Option<i64>, half_life: Option<i64>) -> Result<Json<Commodity>, ApiError> {
+    let name = name.0;
+    let name_clone = name.clone();
+    let dlc_clone = dlc?.odyssey();
+    let include_carriers = include_carriers.unwrap_or(false);
+    if max_age.is_some_and(|max_age| max_age <= 0) || half_life.is_some_and(|half_life| half_life <= 0) {
+        return Err(ApiError::BadRequest("Parameters max_age and half_life must be greater than 0".to_string()));
+    }
+    let commodity = cache.commodity.get_or_load((dlc_clone, (name, include_carriers, max_age, half_life)), || async move {
+        let db = db.get().await?;
+        db.run(move |conn| {
+            let now = history::now();
+            let min_timestamp = max_age.map(|max_age| now - max_age);
+            let half_life = half_life.map(|half_life| half_life as f64);
+
+            //language=postgresql
+            let sql = "
+                WITH markets AS (
+                    SELECT c.buy_price, c.sell_price, c.mean_price, c.timestamp,
+                           CASE WHEN $5::float8 IS NULL THEN 1.0
+                                ELSE power(0.5, greatest($6::int8 - c.timestamp, 0) / $5::float8) END AS weight
+                    FROM commodity c
+                             LEFT JOIN station s ON s.market_id = c.market_id
+                    WHERE c.name = $1 AND c.odyssey = $2
+                      AND ($3 OR NOT coalesce(s.carrier, false))
+                      AND ($4::int8 IS NULL OR c.timestamp >= $4)
+                )
+                SELECT count(*),
+                       min(timestamp),
+                       max(timestamp),
+                       CAST(sum(buy_price * weight) / nullif(sum(weight), 0) AS INTEGER),
+                       CAST(sum(sell_price * weight) / nullif(sum(weight), 0) AS INTEGER),
+                       CAST(sum(mean_price * weight) / nullif(sum(weight), 0) AS INTEGER),
+                       percentile_cont(ARRAY[0.1, 0.5, 0.9]) WITHIN GROUP (ORDER BY buy_price) FILTER (WHERE buy_price > 0),
+                       percentile_cont(ARRAY[0.1, 0.5, 0.9]) WITHIN GROUP (ORDER BY sell_price) FILTER (WHERE sell_price > 0)
+                FROM markets;
+                ";
+            let r = conn.query_one(sql, &[&name_clone, &dlc_clone, &include_carriers, &min_timestamp, &half_life, &now])?;
+            let samples: i64 = r.try_get(0)?;
+            if samples == 0 {
+                return Err(ApiError::NotFound(format!("No market data for commodity {}", name_clone)));
             }
-            None
-        }
-        Some(commodity_history) => {
-            Some(Json(commodity_history))
-        }
+
+            //language=postgresql
+            let sql = "
+                SELECT c.buy_price, s.name, s.system_name
+                FROM commodity c
+                         INNER JOIN station s ON s.market_id = c.market_id
+                WHERE c.name = $1 AND c.odyssey = $2
+                  AND ($3 OR NOT s.carrier)
+                  AND ($4::int8 IS NULL OR c.timestamp >= $4)
+                  AND c.buy_price > 0
+                  AND c.stock > 1000
+                ORDER BY c.buy_price
+                LIMIT 1;
+                ";
+            let lowest_buy_data = match conn.query_opt(sql, &[&name_clone, &dlc_clone, &include_carriers, &min_timestamp])? {
+                Some(row) => json!(
+                    {
+                        "buy_price": row.try_get::<usize,i32>(0)?,
+                        "station": row.try_get::<usize,String>(1)?,
+                        "system": row.try_get::<usize,String>(2)?,
+                    }
+                ),
+                None => Value::Null,
+            };
+
+            //language=postgresql
+            let sql = "
+                SELECT c.sell_price, s.name, s.system_name
+                FROM commodity c
+                         INNER JOIN station s ON s.market_id = c.market_id
+                WHERE c.name = $1 AND c.odyssey = $2
+                  AND ($3 OR NOT s.carrier)
+                  AND ($4::int8 IS NULL OR c.timestamp >= $4)
+                  AND c.sell_price > 0
+                  AND c.demand > 1000
+                ORDER BY c.sell_price DESC
+                LIMIT 1;
+                ";
+            let highest_sell_data = match conn.query_opt(sql, &[&name_clone, &dlc_clone, &include_carriers, &min_timestamp])? {
+                Some(row) => json!(
+                    {
+                        "sell_price": row.try_get::<usize,i32>(0)?,
+                        "station": row.try_get::<usize,String>(1)?,
+                        "system": row.try_get::<usize,String>(2)?,
+                    }
+                ),
+                None => Value::Null,
+            };
+
+            Ok(Commodity {
+                name: Option::from(name_clone),
+                buy_price: r.try_get(3)?,
+                sell_price: r.try_get(4)?,
+                mean_price: r.try_get(5)?,
+                buy_percentiles: Percentiles::from_column(r.try_get(6)?),
+                sell_percentiles: Percentiles::from_column(r.try_get(7)?),
+                samples,
+                oldest_update: r.try_get(1)?,
+                newest_update: r.try_get(2)?,
+                lowest_buy_price: lowest_buy_data,
+                highest_sell_price: highest_sell_data,
+            })
+        }).await
+    }).await;
+    commodity.map(Json)
+}
+
+/// Reads a section of `Rocket.toml`, falling back to the defaults only if the section is missing.
+/// A malformed section gets logged and returns `None`.
+fn config_section<T: Default + for<'de> Deserialize<'de>>(rocket: &Rocket<Build>, section: &str) -> Option<T> {
+    if !rocket.figment().contains(section) {
+        return Some(T::default());
     }
+    rocket.figment().extract_inner(section)
+        .map_err(|e| error!("Invalid [{}] configuration: {}", section, e))
+        .ok()
 }
 
-#[get("/<dlc>/commodity/<name>")]
-async fn commodity(cache: &State<Arc<Mutex<Cache>>>, db: DbConn, name: String, dlc: String) -> Option<Json<Commodity>> {
-    let name_clone = name.clone();
-    let dlc_clone = dlc.contains("odyssey");
-    let some_commodity = cache.lock().unwrap().get_commodity(name.clone());
-    return match some_commodity {
-        None => {
-            let data = db.run(move |conn| {
-                //language=postgresql
-                let sql = "
-                    SELECT DISTINCT
-                                CAST(AVG(buy_price) OVER () as INTEGER) as avg_buy_price,
-                                CAST(AVG(sell_price) OVER () as INTEGER) as avg_sell_price,
-                                CAST(AVG(mean_price) OVER () as INTEGER) as avg_mean_price,
-                                lowest_buy_price,
-                                lowest_buy_station,
-                                lowest_buy_system,
-                                highest_sell_price,
-                                highest_sell_station,
-                                highest_sell_system
-                    FROM commodity
-                             INNER JOIN (
-                        SELECT sell_price as highest_sell_price,
-                               sh.name as highest_sell_station,
-                               sh.system_name as highest_sell_system,
-                               ROW_NUMBER() OVER (ORDER BY sell_price DESC) as rn
-                        FROM commodity hc
-                                 INNER JOIN station sh ON hc.market_id = sh.market_id
-                        WHERE hc.name = $1 AND hc.odyssey = $2
-                          AND sh.name NOT LIKE '___-___'
-                          AND hc.demand > 1000
-                          AND hc.sell_price > 0
-                    ) AS highest_sell
-                                        ON 1=1 -- Dummy join to get a Cartesian product (all combinations)
-                             INNER JOIN (
-                        SELECT CASE WHEN buy_price > 0 THEN buy_price END as lowest_buy_price,
-                               lb.name as lowest_buy_station,
-                               lb.system_name as lowest_buy_system,
-                               ROW_NUMBER() OVER (ORDER BY buy_price) as rn
-                        FROM station lb
-                                 INNER JOIN commodity lowest_buy_commodity ON lb.market_id = lowest_buy_commodity.market_id
-                        WHERE lb.name NOT LIKE '___-___'
-                          AND lowest_buy_commodity.name = $1
-                          AND lowest_buy_commodity.odyssey = $2
-                          AND lowest_buy_commodity.buy_price > 0
-                          AND lowest_buy_commodity.stock > 1000
-                    ) AS lowest_buy
-                                        ON 1=1 -- Dummy join to get a Cartesian product (all combinations)
-                    WHERE name = $1 AND odyssey = $2
-                      AND lowest_buy.rn = 1
-                      AND highest_sell.rn = 1;
-                    ";
-                let optional_row = conn.query_one(sql, &[&name_clone, &dlc_clone]).ok();
-
-                if let Some(r) = optional_row {
-                    let lowest_buy_data = json!(
-                        {
-                            "buy_price": r.get::<usize,i32>(3),
-                            "station": r.get::<usize,String>(4),
-                            "system": r.get::<usize,String>(5),
-                        }
-                    );
-                    let highest_sell_data = json!(
-                        {
-                            "sell_price": r.get::<usize,i32>(6),
-                            "station": r.get::<usize,String>(7),
-                            "system": r.get::<usize,String>(8),
-                        }
-                    );
-                    let commodity = Commodity {
-                        name: Option::from(name_clone),
-                        buy_price: r.get(0),
-                        sell_price: r.get(1),
-                        mean_price: r.get(2),
-                        lowest_buy_price: lowest_buy_data,
-                        highest_sell_price: highest_sell_data,
-                    };
-                    return Some(commodity);
-                }
-                None
-            }).await;
+/// Mounts every data route. Ignition fails if the `[cache]` or `[route]` section of `Rocket.toml` is malformed.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Data Stage", |rocket| async {
+        let (Some(config), Some(route_config)) = (config_section::<CacheConfig>(&rocket, "cache"), config_section::<RouteConfig>(&rocket, "route")) else {
+            return Err(rocket);
+        };
+        let cache = Arc::new(Cache::new(&config));
+        let eviction_cache = cache.clone();
+        let eviction_interval = Duration::from_secs(config.eviction_interval.max(1));
+
+        Ok(rocket.attach(DbConn::fairing())
+            .manage(cache)
+            .manage(route_config)
+            .mount("/data", routes![root,cache_stats,commodity,commodity_history,system,system_by_name])
+            .mount("/data", systems::routes())
+            .mount("/data", catalogue::routes())
+            .mount("/data", history::routes())
+            .mount("/data", station::routes())
+            .mount("/data", market::routes())
+            .mount("/data", trade::routes())
+            .mount("/data", tree::routes())
+            .mount("/data", body::routes())
+            .mount("/data", valuation::routes())
+            .mount("/data", orbit::routes())
+            .mount("/data", route::routes())
+            .register("/data", catchers![error::default_catcher])
+            .attach(AdHoc::on_liftoff("Cache Eviction", move |_| Box::pin(async move {
+                rocket::tokio::spawn(async move {
+                    let mut interval = rocket::tokio::time::interval(eviction_interval);
+                    loop {
+                        interval.tick().await;
+                        eviction_cache.evict_expired();
+                    }
+                });
+            }))))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use rocket_sync_db_pools::postgres::config::{Host, SslMode};
+    use rocket_sync_db_pools::postgres::{self, NoTls};
+
+    use super::load_system;
+
+    /// Addresses of the seeded systems, far away from any real system address
+    const SMALL_SYSTEM: i64 = -4_000_001;
+    const LARGE_SYSTEM: i64 = -4_000_002;
 
-            if let Some(commodity) = data {
-                cache.lock().unwrap().put_commodity(commodity.clone(), name.clone());
-                return Some(Json(commodity));
+    fn database_config() -> postgres::Config {
+        let url = std::env::var("EDCAS_TEST_DATABASE_URL").expect("EDCAS_TEST_DATABASE_URL must point to a database with the edcas schema");
+        url.parse().expect("EDCAS_TEST_DATABASE_URL is no valid postgres url")
+    }
+
+    /// Forwards a single connection to the database and counts the statements run over it,
+    /// which are the `Execute` messages of the extended protocol and the `Query` messages of the simple one.
+    fn counting_proxy(config: &postgres::Config) -> (postgres::Config, Arc<AtomicUsize>) {
+        let host = match config.get_hosts().first() {
+            Some(Host::Tcp(host)) => host.clone(),
+            _ => "localhost".to_string(),
+        };
+        let port = config.get_ports().first().copied().unwrap_or(5432);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let statements = Arc::new(AtomicUsize::new(0));
+
+        let mut proxied = postgres::Config::new();
+        proxied.host("127.0.0.1").port(listener.local_addr().unwrap().port()).ssl_mode(SslMode::Disable);
+        if let Some(user) = config.get_user() {
+            proxied.user(user);
+        }
+        if let Some(password) = config.get_password() {
+            proxied.password(password);
+        }
+        if let Some(dbname) = config.get_dbname() {
+            proxied.dbname(dbname);
+        }
+
+        let counter = statements.clone();
+        thread::spawn(move || {
+            let (mut client, _) = listener.accept().unwrap();
+            let mut server = TcpStream::connect((host, port)).unwrap();
+            let mut server_read = server.try_clone().unwrap();
+            let mut client_write = client.try_clone().unwrap();
+            thread::spawn(move || std::io::copy(&mut server_read, &mut client_write));
+
+            //The startup message is the only one without a type byte
+            let mut typed = false;
+            loop {
+                let mut header = vec![0u8; if typed { 5 } else { 4 }];
+                if client.read_exact(&mut header).is_err() {
+                    break;
+                }
+                let length = i32::from_be_bytes(header[header.len() - 4..].try_into().unwrap()) as usize;
+                let mut body = vec![0u8; length - 4];
+                if client.read_exact(&mut body).is_err() {
+                    break;
+                }
+                if typed && matches!(header[0], b'E' | b'Q') {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+                header.extend(body);
+                if server.write_all(&header).is_err() {
+                    break;
+                }
+                typed = true;
             }
+        });
+        (proxied, statements)
+    }
+
+    /// Seeded test systems, removed again when dropped.
+    struct Seed(postgres::Client);
 
-            return None;
+    impl Seed {
+        fn new(config: &postgres::Config) -> Self {
+            let mut seed = Seed(config.connect(NoTls).unwrap());
+            seed.clean();
+            seed.system(SMALL_SYSTEM, 1, 1);
+            seed.system(LARGE_SYSTEM, 3, 40);
+            seed
         }
-        Some(commodity) => {
-            Some(Json(commodity))
+
+        /// A system with the given number of stars and planets, every body orbiting the first star.
+        fn system(&mut self, address: i64, stars: i32, planets: i32) {
+            let client = &mut self.0;
+            client.execute("insert into system (address, odyssey, name, x, y, z) values ($1, true, 'Query count test', 0, 0, 0)", &[&address]).unwrap();
+            client.execute("insert into star (system_address, odyssey, id, name, type, stellar_mass)
+                select $1, true, g, 'Test star ' || g, 'K', 0.8 from generate_series(0, $2::int4 - 1) g", &[&address, &stars]).unwrap();
+            client.execute("insert into body (system_address, odyssey, id, name, class, mass_em)
+                select $1, true, g, 'Test planet ' || g, 'Icy body', 0.1 from generate_series($2::int4, $2::int4 + $3::int4 - 1) g", &[&address, &stars, &planets]).unwrap();
+            client.execute("insert into parent (system_address, body_id, parent_type, parent_id)
+                select $1, g, 'Star', 0 from generate_series(1, $2::int4 + $3::int4 - 1) g", &[&address, &stars, &planets]).unwrap();
         }
-    };
-}
 
-    pub fn stage() -> AdHoc {
-        let cache = Cache {
-            commodity_history: HashMap::new(),
-            commodity: HashMap::new(),
-            system: HashMap::new(),
-        };
+        fn clean(&mut self) {
+            let addresses = vec![SMALL_SYSTEM, LARGE_SYSTEM];
+            for table in ["system where address", "star where system_address", "body where system_address", "parent where system_address"] {
+                self.0.execute(format!("delete from {} = any($1)", table).as_str(), &[&addresses]).unwrap();
+            }
+        }
+    }
 
-        let cache_mutex = Arc::new(Mutex::new(cache));
+    impl Drop for Seed {
+        fn drop(&mut self) {
+            self.clean();
+        }
+    }
 
-        AdHoc::on_ignite("Data Stage", |rocket| async {
-            rocket.attach(DbConn::fairing()).manage(cache_mutex).mount("/data", routes![root,commodity,commodity_history,system])
-        })
-    }
\ No newline at end of file
+    #[test]
+    #[ignore = "needs a database with the edcas schema, set EDCAS_TEST_DATABASE_URL and run with --ignored"]
+    fn system_takes_the_same_number_of_queries_regardless_of_its_size() {
+        let config = database_config();
+        let _seed = Seed::new(&config);
+        let (proxied, statements) = counting_proxy(&config);
+        let mut client = proxied.connect(NoTls).unwrap();
+
+        for (address, bodies) in [(SMALL_SYSTEM, 2), (LARGE_SYSTEM, 43)] {
+            statements.store(0, Ordering::SeqCst);
+            let system = load_system(&mut client, address, true).unwrap();
+            let loaded = system.stars.unwrap_or_default().len() + system.planets.unwrap_or_default().len();
+
+            assert_eq!(loaded, bodies);
+            assert_eq!(statements.load(Ordering::SeqCst), 4, "queries to load system {} with {} bodies", address, bodies);
+        }
+    }
+}
@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use rocket::{form, Route, State};
+use rocket::serde::{Deserialize, Serialize, json::Json};
+use rocket_sync_db_pools::postgres;
+use rocket_sync_db_pools::postgres::types::ToSql;
+use serde_json::Value;
+
+use super::dlc::Dlc;
+use super::error::{query_param, ApiError};
+use super::market::CursorPage;
+use super::parent::{Parent, ParentFormat};
+use super::systems::{check_radius, distance_sql, load_coordinates, within_radius_sql, SystemSummary};
+use super::{load_parents, load_planets, load_stars, page_bounds, planet_from_row, star_from_row, Cache, LazyDbConn, Planet, Star,
+            PLANET_COLUMNS, STAR_COLUMNS};
+
+/// A single star or planet.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", tag = "kind", rename_all = "lowercase")]
+pub enum Body {
+    Star(Box<Star>),
+    Planet(Box<Planet>),
+}
+
+/// Short reference to another star or planet of the same system.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BodyRef {
+    pub body_id: i32,
+    pub body_name: Option<String>,
+    pub kind: BodyKind,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BodyDetail {
+    #[serde(flatten)]
+    pub body: Body,
+    /// Bodies orbiting the same direct parent
+    pub siblings: Vec<BodyRef>,
+    /// Bodies orbiting this one directly
+    pub children: Vec<BodyRef>,
+}
+
+/// One star or planet of a system, discriminated by `kind`, together with its siblings and children.
+///
+/// Barycentres and rings are no bodies of their own, so siblings and children only include stars and planets.
+/// A body orbiting nothing has the other bodies orbiting nothing as siblings. `parents=journal` returns the
+/// parents in the journal format.
+#[get("/<dlc>/system/<address>/body/<body_id>?<parents>")]
+async fn body(cache: &State<Arc<Cache>>, db: LazyDbConn<'_>, address: i64, body_id: i32, dlc: Result<Dlc, ApiError>,
+              parents: form::Result<'_, ParentFormat>) -> Result<Json<Value>, ApiError> {
+    let odyssey = dlc?.odyssey();
+    let parents = query_param("parents", parents)?.unwrap_or_default();
+    let body = cache.body.get_or_load((odyssey, (address, body_id)), || async move {
+        let db = db.get().await?;
+        db.run(move |conn| load_body(conn, address, body_id, odyssey)).await
+    }).await?;
+    parents.apply(&body).map(Json)
+}
+
+fn load_body(conn: &mut postgres::Client, address: i64, body_id: i32, odyssey: bool) -> Result<BodyDetail, ApiError> {
+    let mut parents = load_parents(conn, address)?;
+    let orbits = |id: i32| parents.get(&id).and_then(|parents| parents.first()).map(|parent| parent.body_id());
+
+    //language=postgresql
+    let sql = "select id,name,true from star where system_address = $1 and odyssey = $2
+        union all
+        select id,name,false from body where system_address = $1 and odyssey = $2
+        order by 1";
+    let mut siblings = vec![];
+    let mut children = vec![];
+    let parent = orbits(body_id);
+    for row in conn.query(sql, &[&address, &odyssey])? {
+        let id: i32 = row.try_get(0)?;
+        if id == body_id {
+            continue;
+        }
+        let kind = if row.try_get(2)? { BodyKind::Star } else { BodyKind::Planet };
+        let body = BodyRef { body_id: id, body_name: row.try_get(1)?, kind };
+        match orbits(id) {
+            Some(orbited) if orbited == body_id => children.push(body),
+            orbited if orbited == parent => siblings.push(body),
+            _ => {}
+        }
+    }
+
+    let body = match load_stars(conn, address, odyssey, Some(body_id), &mut parents)?.pop() {
+        Some(star) => Body::Star(Box::new(star)),
+        None => load_planets(conn, address, odyssey, Some(body_id), &mut parents)?.pop()
+            .map(|planet| Body::Planet(Box::new(planet)))
+            .ok_or_else(|| ApiError::NotFound(format!("Body {} not found in system {}", body_id, address)))?,
+    };
+    Ok(BodyDetail { body, siblings, children })
+}
+
+/// Whether a body is a star or a planet, also the table searched by [`search`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum BodyKind {
+    #[field(value = "star")]
+    Star,
+    #[field(value = "planet")]
+    Planet,
+}
+
+/// Filters of the body search. Text is compared case insensitive, ranges include their bounds.
+#[derive(Debug, Clone, Default, FromForm)]
+pub struct BodyFilter {
+    pub planet_class: Option<String>,
+    pub atmosphere: Option<String>,
+    pub volcanism: Option<String>,
+    pub terraform_state: Option<String>,
+    pub landable: Option<bool>,
+    /// Surface gravity as stored, in m/s²
+    pub min_gravity: Option<f32>,
+    pub max_gravity: Option<f32>,
+    pub star_type: Option<String>,
+    pub min_subclass: Option<i32>,
+    pub max_subclass: Option<i32>,
+}
+
+impl BodyFilter {
+    fn has_planet_filters(&self) -> bool {
+        self.planet_class.is_some() || self.atmosphere.is_some() || self.volcanism.is_some() || self.terraform_state.is_some()
+            || self.landable.is_some() || self.min_gravity.is_some() || self.max_gravity.is_some()
+    }
+
+    fn has_star_filters(&self) -> bool {
+        self.star_type.is_some() || self.min_subclass.is_some() || self.max_subclass.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BodyHit {
+    #[serde(flatten)]
+    pub body: Body,
+    pub system: SystemSummary,
+    /// Light years to the `from` system
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance: Option<f64>,
+}
+
+/// Position in the body search: distance to the `from` system if given, system address and body id of the last result.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SearchCursor {
+    distance: Option<f64>,
+    address: i64,
+    body_id: i32,
+}
+
+impl SearchCursor {
+    /// Parses `<address>_<body id>`, or `<distance>_<address>_<body id>` for searches `nearest` to a system.
+    fn parse(cursor: &str, nearest: bool) -> Result<Self, ApiError> {
+        let invalid = || ApiError::BadRequest(format!("Invalid cursor '{}'", cursor));
+        let parts: Vec<&str> = cursor.split('_').collect();
+        let (distance, address, body_id) = match (nearest, parts.as_slice()) {
+            (false, [address, body_id]) => (None, address, body_id),
+            (true, [distance, address, body_id]) => {
+                let distance = distance.parse::<f64>().ok().filter(|distance| distance.is_finite()).ok_or_else(invalid)?;
+                (Some(distance), address, body_id)
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(SearchCursor {
+            distance,
+            address: address.parse().map_err(|_| invalid())?,
+            body_id: body_id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl std::fmt::Display for SearchCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.distance {
+            Some(distance) => write!(f, "{}_{}_{}", distance, self.address, self.body_id),
+            None => write!(f, "{}_{}", self.address, self.body_id),
+        }
+    }
+}
+
+/// Distance column, spatial predicate, keyset condition and order of a body search on the table aliased as `table`,
+/// with its parameters numbered from `first`.
+///
+/// Without `from` the parameters are the cursor address and body id and the limit, nearest first they are
+/// the coordinates and radius, the cursor distance, address and body id and the limit.
+fn search_order(table: &str, nearest: bool, first: usize) -> (String, String) {
+    let p = |index: usize| format!("${}", first + index);
+    if !nearest {
+        let clause = format!("and ({a}::int8 is null or ({table}.system_address, {table}.id) > ({a}, {b}::int4))
+            order by {table}.system_address, {table}.id
+            limit {c}", a = p(0), b = p(1), c = p(2));
+        return ("null::float8".to_string(), clause);
+    }
+    let (distance, within_radius) = (distance_sql(first), within_radius_sql(first, first + 3));
+    let clause = format!("and {within_radius}
+            and ({d}::float8 is null or ({distance}, {table}.system_address, {table}.id) > ({d}, {a}::int8, {b}::int4))
+            order by distance, {table}.system_address, {table}.id
+            limit {l}", d = p(4), a = p(5), b = p(6), l = p(7));
+    (distance, clause)
+}
+
+/// Stars or planets matching the filters, optionally within `radius` light years of the `from` system.
+///
+/// Searches planets unless `kind=star` is given or only star filters are used. Results are ordered
+/// nearest first if `from` is given, otherwise by system address and body id. Pass `next_cursor` as `cursor`
+/// with the same parameters to get the next page. `parents=journal` returns the parents in the journal format.
+#[get("/<dlc>/bodies/search?<kind>&<from>&<radius>&<cursor>&<limit>&<parents>&<filter..>")]
+#[allow(clippy::too_many_arguments)]
+async fn search(db: LazyDbConn<'_>, dlc: Result<Dlc, ApiError>, kind: form::Result<'_, BodyKind>, from: form::Result<'_, i64>,
+                radius: form::Result<'_, f64>, cursor: Option<&str>, limit: form::Result<'_, i64>, parents: form::Result<'_, ParentFormat>,
+                filter: BodyFilter) -> Result<Json<Value>, ApiError> {
+    let odyssey = dlc?.odyssey();
+    let parents = query_param("parents", parents)?.unwrap_or_default();
+    let (from, radius) = (query_param("from", from)?, query_param("radius", radius)?);
+    let (limit, _) = page_bounds(query_param("limit", limit)?, None)?;
+    let kind = query_param("kind", kind)?.unwrap_or(if filter.has_star_filters() && !filter.has_planet_filters() { BodyKind::Star } else { BodyKind::Planet });
+    match kind {
+        BodyKind::Star if filter.has_planet_filters() => return Err(ApiError::BadRequest("Planet filters cannot be used to search stars".to_string())),
+        BodyKind::Planet if filter.has_star_filters() => return Err(ApiError::BadRequest("Star filters cannot be used to search planets".to_string())),
+        _ => {}
+    }
+    let radius = match (from, radius) {
+        (Some(_), radius) => Some(check_radius(radius.unwrap_or(50.0))?),
+        (None, Some(_)) => return Err(ApiError::BadRequest("Parameter radius requires from".to_string())),
+        (None, None) => None,
+    };
+    let cursor = cursor.map(|cursor| SearchCursor::parse(cursor, from.is_some())).transpose()?;
+
+    let db = db.get().await?;
+    let page = db.run(move |conn| {
+        let origin = from.map(|address| load_coordinates(conn, address, odyssey)).transpose()?;
+        let (cursor_distance, cursor_address, cursor_body_id) = (cursor.and_then(|cursor| cursor.distance),
+            cursor.map(|cursor| cursor.address), cursor.map(|cursor| cursor.body_id));
+        let fetch = limit + 1;
+
+        //The filters come first, the parameters of the order follow them
+        let mut params: Vec<&(dyn ToSql + Sync)> = vec![&odyssey];
+        let sql = match kind {
+            BodyKind::Star => {
+                params.extend([&filter.star_type as &(dyn ToSql + Sync), &filter.min_subclass, &filter.max_subclass]);
+                let (distance, order) = search_order("st", origin.is_some(), params.len() + 1);
+                //language=postgresql
+                format!("select {STAR_COLUMNS},sy.name,sy.address,sy.x,sy.y,sy.z,{distance} as distance
+                    from star st
+                        inner join system sy on sy.address = st.system_address and sy.odyssey = st.odyssey
+                    where st.odyssey = $1
+                      and ($2::text is null or lower(st.type) = lower($2))
+                      and ($3::int4 is null or st.subclass >= $3)
+                      and ($4::int4 is null or st.subclass <= $4)
+                      {order}")
+            }
+            BodyKind::Planet => {
+                params.extend([&filter.planet_class as &(dyn ToSql + Sync), &filter.atmosphere, &filter.volcanism, &filter.terraform_state,
+                    &filter.landable, &filter.min_gravity, &filter.max_gravity]);
+                let (distance, order) = search_order("b", origin.is_some(), params.len() + 1);
+                //language=postgresql
+                format!("select {PLANET_COLUMNS},sy.name,sy.address,sy.x,sy.y,sy.z,{distance} as distance
+                    from body b
+                        inner join system sy on sy.address = b.system_address and sy.odyssey = b.odyssey
+                    where b.odyssey = $1
+                      and ($2::text is null or lower(b.class) = lower($2))
+                      and ($3::text is null or lower(b.atmosphere) = lower($3))
+                      and ($4::text is null or lower(b.volcanism) = lower($4))
+                      and ($5::text is null or lower(b.terraform_state) = lower($5))
+                      and ($6::bool is null or b.landable = $6)
+                      and ($7::real is null or b.surface_gravity >= $7)
+                      and ($8::real is null or b.surface_gravity <= $8)
+                      {order}")
+            }
+        };
+        if let Some(origin) = &origin {
+            params.extend([&origin.x as &(dyn ToSql + Sync), &origin.y, &origin.z, &radius, &cursor_distance]);
+        }
+        params.extend([&cursor_address as &(dyn ToSql + Sync), &cursor_body_id, &fetch]);
+        let rows = conn.query(sql.as_str(), &params)?;
+
+        //Columns after the body ones, which differ in number between stars and planets
+        let system_column = match kind {
+            BodyKind::Star => 22,
+            BodyKind::Planet => 25,
+        };
+        let keys: Vec<(i64, i32)> = rows.iter()
+            .map(|row| Ok((row.try_get(system_column + 1)?, row.try_get(1)?)))
+            .collect::<Result<_, ApiError>>()?;
+        let mut parents = load_parents_of(conn, &keys)?;
+
+        let mut hits = vec![];
+        for (row, key) in rows.iter().zip(keys) {
+            let body_parents = parents.remove(&key).unwrap_or_default();
+            let body = match kind {
+                BodyKind::Star => Body::Star(Box::new(star_from_row(row, body_parents))),
+                BodyKind::Planet => Body::Planet(Box::new(planet_from_row(row, body_parents, odyssey))),
+            };
+            hits.push((key, BodyHit {
+                body,
+                system: SystemSummary {
+                    name: row.try_get(system_column)?,
+                    address: key.0,
+                    x: row.try_get(system_column + 2)?,
+                    y: row.try_get(system_column + 3)?,
+                    z: row.try_get(system_column + 4)?,
+                },
+                distance: row.try_get(system_column + 5)?,
+            }));
+        }
+
+        let mut next_cursor = None;
+        if hits.len() as i64 > limit {
+            hits.truncate(limit as usize);
+            next_cursor = hits.last().map(|((address, body_id), hit)| SearchCursor { distance: hit.distance, address: *address, body_id: *body_id }.to_string());
+        }
+        let results = hits.into_iter().map(|(_, hit)| hit).collect();
+        Ok::<_, ApiError>(CursorPage { results, next_cursor })
+    }).await?;
+    parents.apply(&page).map(Json)
+}
+
+/// Parents of bodies spread over any number of systems, keyed by system address and body id.
+fn load_parents_of(conn: &mut postgres::Client, bodies: &[(i64, i32)]) -> Result<HashMap<(i64, i32), Vec<Parent>>, ApiError> {
+    let addresses: Vec<i64> = bodies.iter().map(|(address, _)| *address).collect();
+    let body_ids: Vec<i32> = bodies.iter().map(|(_, body_id)| *body_id).collect();
+    let mut parents: HashMap<(i64, i32), Vec<Parent>> = HashMap::new();
+
+    //language=postgresql
+    let sql = "select p.system_address,p.body_id,p.parent_type,p.parent_id
+        from parent p
+            inner join unnest($1::int8[], $2::int4[]) as wanted(system_address, body_id)
+                on wanted.system_address = p.system_address and wanted.body_id = p.body_id
+        order by p.id";
+    for row in conn.query(sql, &[&addresses, &body_ids])? {
+        let (address, body_id, kind, parent_id): (i64, i32, &str, i32) = (row.try_get(0)?, row.try_get(1)?, row.try_get(2)?, row.try_get(3)?);
+        match Parent::from_journal(kind, parent_id) {
+            Some(parent) => parents.entry((address, body_id)).or_default().push(parent),
+            None => warn!("Skipping unknown parent type '{}' of body {} in system {}", kind, body_id, address),
+        }
+    }
+    Ok(parents)
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![body, search]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trips() {
+        for (cursor, nearest) in [(SearchCursor { distance: None, address: 10477373803, body_id: 3 }, false),
+                                  (SearchCursor { distance: Some(4.378881177848481), address: 1, body_id: 0 }, true)] {
+            assert_eq!(SearchCursor::parse(&cursor.to_string(), nearest).unwrap(), cursor);
+        }
+    }
+
+    #[test]
+    fn cursor_must_match_the_search() {
+        assert!(SearchCursor::parse("1_2", true).is_err());
+        assert!(SearchCursor::parse("0.5_1_2", false).is_err());
+        assert!(SearchCursor::parse("NaN_1_2", true).is_err());
+        assert!(SearchCursor::parse("1_x", false).is_err());
+    }
+}
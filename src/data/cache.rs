@@ -0,0 +1,468 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::sync::OnceCell;
+
+/// Number of independently locked shards of a [`SharedCache`]
+const SHARDS: usize = 16;
+
+/// Time to live and capacity of a single cache, read from `Rocket.toml`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct CacheSettings {
+    /// Seconds an entry stays valid
+    pub ttl: u64,
+    /// Maximum number of entries before the least recently used one gets dropped
+    pub capacity: usize,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        CacheSettings {
+            ttl: 600,
+            capacity: 10_000,
+        }
+    }
+}
+
+/// The `[cache]` section of `Rocket.toml`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct CacheConfig {
+    /// Seconds between two runs of the background eviction
+    pub eviction_interval: u64,
+    pub system: CacheSettings,
+    /// System addresses by name, for the lookup by name
+    pub system_address: CacheSettings,
+    pub body: CacheSettings,
+    pub commodity: CacheSettings,
+    pub commodity_history: CacheSettings,
+    pub commodity_candles: CacheSettings,
+    pub commodity_movers: CacheSettings,
+    pub commodities: CacheSettings,
+    pub station: CacheSettings,
+    pub system_stations: CacheSettings,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            eviction_interval: 60,
+            system: CacheSettings::default(),
+            system_address: CacheSettings::default(),
+            body: CacheSettings::default(),
+            commodity: CacheSettings::default(),
+            commodity_history: CacheSettings::default(),
+            commodity_candles: CacheSettings::default(),
+            commodity_movers: CacheSettings::default(),
+            commodities: CacheSettings::default(),
+            station: CacheSettings::default(),
+            system_stations: CacheSettings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CacheStats {
+    pub entries: usize,
+    pub capacity: usize,
+    pub ttl: u64,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+struct Entry<V> {
+    instant: Instant,
+    tick: u64,
+    data: V,
+}
+
+/// Size capped cache with a time to live per entry.
+///
+/// Once the capacity is reached the least recently used entry gets dropped. Expired entries are
+/// never returned and get removed either on access or by [`TtlCache::evict_expired`].
+pub struct TtlCache<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    //Access tick -> key, the first entry is the least recently used one
+    usage: BTreeMap<u64, K>,
+    tick: u64,
+    ttl: Duration,
+    capacity: usize,
+    hits: u64,
+    misses: u64,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> TtlCache<K, V> {
+    pub fn new(settings: CacheSettings) -> Self {
+        TtlCache {
+            entries: HashMap::new(),
+            usage: BTreeMap::new(),
+            tick: 0,
+            ttl: Duration::from_secs(settings.ttl),
+            capacity: settings.capacity,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let expired = match self.entries.get(key) {
+            None => {
+                //Not cached yet
+                self.misses += 1;
+                return None;
+            }
+            Some(entry) => entry.instant.elapsed() > self.ttl,
+        };
+
+        if expired {
+            //Cache too old -> drop it and send nothing
+            self.remove(key);
+            self.misses += 1;
+            return None;
+        }
+
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self.entries.get_mut(key)?;
+        self.usage.remove(&entry.tick);
+        self.usage.insert(tick, key.clone());
+        entry.tick = tick;
+        self.hits += 1;
+        Some(entry.data.clone())
+    }
+
+    /// Like [`TtlCache::get`] without touching the usage order or the hit/miss counters.
+    pub fn peek(&self, key: &K) -> Option<V> {
+        self.entries.get(key)
+            .filter(|entry| entry.instant.elapsed() <= self.ttl)
+            .map(|entry| entry.data.clone())
+    }
+
+    pub fn put(&mut self, key: K, data: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(&key);
+        while self.entries.len() >= self.capacity {
+            match self.usage.pop_first() {
+                Some((_, oldest)) => {
+                    self.entries.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+
+        self.tick += 1;
+        self.usage.insert(self.tick, key.clone());
+        self.entries.insert(key, Entry {
+            instant: Instant::now(),
+            tick: self.tick,
+            data,
+        });
+    }
+
+    /// Drops every expired entry and returns how many were removed.
+    pub fn evict_expired(&mut self) -> usize {
+        let ttl = self.ttl;
+        let expired: Vec<K> = self.entries.iter()
+            .filter(|(_, entry)| entry.instant.elapsed() > ttl)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &expired {
+            self.remove(key);
+        }
+        expired.len()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.entries.len(),
+            capacity: self.capacity,
+            ttl: self.ttl.as_secs(),
+            hits: self.hits,
+            misses: self.misses,
+        }
+    }
+
+    fn remove(&mut self, key: &K) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.usage.remove(&entry.tick);
+        }
+    }
+}
+
+/// Result of a running load, shared by every request waiting on it
+type InFlight<V, E> = OnceCell<Result<V, E>>;
+
+/// Concurrent cache made up of independently locked [`TtlCache`] shards.
+///
+/// The configured capacity is split over the shards so their total matches it exactly. Eviction is least
+/// recently used per shard, so a full cache may drop an entry which is not the oldest one overall.
+/// Caches smaller than [`SHARDS`] get one shard per entry.
+///
+/// Concurrent misses for the same key are coalesced: only one caller runs the loader while every
+/// other caller waits for and shares its result, including a failed one.
+pub struct SharedCache<K, V, E> {
+    shards: Vec<Mutex<TtlCache<K, V>>>,
+    in_flight: Mutex<HashMap<K, Arc<InFlight<V, E>>>>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone, E: Clone> SharedCache<K, V, E> {
+    pub fn new(settings: CacheSettings) -> Self {
+        let shards = settings.capacity.clamp(1, SHARDS);
+        let shard_settings = |index: usize| CacheSettings {
+            ttl: settings.ttl,
+            //Spread the remainder over the first shards
+            capacity: settings.capacity / shards + usize::from(index < settings.capacity % shards),
+        };
+        SharedCache {
+            shards: (0..shards).map(|index| Mutex::new(TtlCache::new(shard_settings(index)))).collect(),
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        lock(self.shard(key)).get(key)
+    }
+
+    pub fn put(&self, key: K, data: V) {
+        lock(self.shard(&key)).put(key, data);
+    }
+
+    /// Returns the cached value or runs `load` to fetch it.
+    ///
+    /// Only successfully loaded values get cached. An error is shared with the callers waiting
+    /// on the same load, but the next request will try again.
+    pub async fn get_or_load<F, Fut>(&self, key: K, load: F) -> Result<V, E>
+        where F: FnOnce() -> Fut, Fut: Future<Output=Result<V, E>> {
+        if let Some(data) = self.get(&key) {
+            return Ok(data);
+        }
+
+        let cell = lock(&self.in_flight).entry(key.clone()).or_default().clone();
+        let _guard = InFlightGuard { cache: self, key: &key, cell: &cell };
+
+        cell.get_or_init(|| async {
+            //Another request may have filled the cache between the miss and joining the load
+            if let Some(data) = lock(self.shard(&key)).peek(&key) {
+                return Ok(data);
+            }
+            let data = load().await?;
+            self.put(key.clone(), data.clone());
+            Ok(data)
+        }).await.clone()
+    }
+
+    pub fn evict_expired(&self) -> usize {
+        self.shards.iter().map(|shard| lock(shard).evict_expired()).sum()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.shards.iter().fold(CacheStats {
+            entries: 0,
+            capacity: 0,
+            ttl: 0,
+            hits: 0,
+            misses: 0,
+        }, |total, shard| {
+            let stats = lock(shard).stats();
+            CacheStats {
+                entries: total.entries + stats.entries,
+                capacity: total.capacity + stats.capacity,
+                ttl: stats.ttl,
+                hits: total.hits + stats.hits,
+                misses: total.misses + stats.misses,
+            }
+        })
+    }
+
+    fn shard(&self, key: &K) -> &Mutex<TtlCache<K, V>> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+}
+
+/// Removes a finished load from the in flight map, even if the loading request got cancelled.
+struct InFlightGuard<'a, K: Hash + Eq + Clone, V: Clone, E: Clone> {
+    cache: &'a SharedCache<K, V, E>,
+    key: &'a K,
+    cell: &'a Arc<InFlight<V, E>>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone, E: Clone> Drop for InFlightGuard<'_, K, V, E> {
+    fn drop(&mut self) {
+        let mut in_flight = lock(&self.cache.in_flight);
+        if let Some(current) = in_flight.get(self.key) {
+            //Keep the load around as long as someone else is still waiting on it
+            if Arc::ptr_eq(current, self.cell) && (self.cell.initialized() || Arc::strong_count(self.cell) <= 2) {
+                in_flight.remove(self.key);
+            }
+        }
+    }
+}
+
+/// A panic while holding a cache lock must not take down every other request, so poisoning is ignored.
+/// The cache itself is never left half updated by a panic.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread::sleep;
+
+    use rocket::futures::future::join_all;
+    use rocket::tokio::time;
+
+    use super::*;
+
+    fn settings(ttl: u64, capacity: usize) -> CacheSettings {
+        CacheSettings { ttl, capacity }
+    }
+
+    #[test]
+    fn odyssey_and_horizons_entries_are_kept_apart() {
+        let cache: SharedCache<(bool, String), i32, ()> = SharedCache::new(settings(600, 100));
+        cache.put((true, "gold".to_string()), 1);
+        cache.put((false, "gold".to_string()), 2);
+
+        assert_eq!(cache.get(&(true, "gold".to_string())), Some(1));
+        assert_eq!(cache.get(&(false, "gold".to_string())), Some(2));
+        assert_eq!(cache.get(&(false, "silver".to_string())), None);
+    }
+
+    #[rocket::async_test]
+    async fn odyssey_and_horizons_loads_are_kept_apart() {
+        let cache: SharedCache<(bool, i64), &str, ()> = SharedCache::new(settings(600, 100));
+        let odyssey = cache.get_or_load((true, 42), || async { Ok("odyssey") }).await;
+        let horizons = cache.get_or_load((false, 42), || async { Ok("horizons") }).await;
+
+        assert_eq!(odyssey, Ok("odyssey"));
+        assert_eq!(horizons, Ok("horizons"));
+        assert_eq!(cache.get(&(true, 42)), Some("odyssey"));
+        assert_eq!(cache.get(&(false, 42)), Some("horizons"));
+    }
+
+    #[test]
+    fn expired_entries_are_not_returned() {
+        let mut cache = TtlCache::new(settings(0, 10));
+        cache.put("gold", 1);
+        sleep(Duration::from_millis(5));
+
+        assert_eq!(cache.peek(&"gold"), None);
+        assert_eq!(cache.get(&"gold"), None);
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn evict_expired_removes_only_expired_entries() {
+        let mut cache = TtlCache::new(settings(0, 10));
+        cache.put("gold", 1);
+        cache.put("silver", 2);
+        sleep(Duration::from_millis(5));
+        assert_eq!(cache.evict_expired(), 2);
+
+        let mut cache = TtlCache::new(settings(600, 10));
+        cache.put("gold", 1);
+        assert_eq!(cache.evict_expired(), 0);
+        assert_eq!(cache.get(&"gold"), Some(1));
+    }
+
+    #[test]
+    fn least_recently_used_entry_is_dropped_at_capacity() {
+        let mut cache = TtlCache::new(settings(600, 2));
+        cache.put("gold", 1);
+        cache.put("silver", 2);
+        //Touching gold leaves silver as the least recently used entry
+        assert_eq!(cache.get(&"gold"), Some(1));
+        cache.put("tea", 3);
+
+        assert_eq!(cache.get(&"silver"), None);
+        assert_eq!(cache.get(&"gold"), Some(1));
+        assert_eq!(cache.get(&"tea"), Some(3));
+        assert_eq!(cache.stats().entries, 2);
+    }
+
+    #[test]
+    fn replacing_an_entry_does_not_evict_others() {
+        let mut cache = TtlCache::new(settings(600, 2));
+        cache.put("gold", 1);
+        cache.put("silver", 2);
+        cache.put("gold", 3);
+
+        assert_eq!(cache.get(&"gold"), Some(3));
+        assert_eq!(cache.get(&"silver"), Some(2));
+    }
+
+    #[test]
+    fn hits_and_misses_are_counted() {
+        let mut cache = TtlCache::new(settings(600, 10));
+        cache.put("gold", 1);
+        cache.get(&"gold");
+        cache.get(&"gold");
+        cache.get(&"silver");
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (2, 1));
+    }
+
+    #[test]
+    fn shards_add_up_to_the_configured_capacity() {
+        for capacity in [0, 1, 10, 16, 100, 2000, 10_001] {
+            let cache: SharedCache<i64, i64, ()> = SharedCache::new(settings(600, capacity));
+            assert_eq!(cache.stats().capacity, capacity);
+            assert!(capacity == 0 || cache.shards.iter().all(|shard| lock(shard).capacity > 0));
+        }
+    }
+
+    #[test]
+    fn small_caches_never_hold_more_than_their_capacity() {
+        let cache: SharedCache<i64, i64, ()> = SharedCache::new(settings(600, 10));
+        for key in 0..1000 {
+            cache.put(key, key);
+        }
+        assert_eq!(cache.stats().entries, 10);
+    }
+
+    #[rocket::async_test]
+    async fn concurrent_loads_of_the_same_key_run_once() {
+        let cache: SharedCache<(bool, i64), i64, ()> = SharedCache::new(settings(600, 100));
+        let loads = AtomicUsize::new(0);
+
+        let results = join_all((0..20).map(|_| cache.get_or_load((true, 1), || async {
+            loads.fetch_add(1, Ordering::SeqCst);
+            time::sleep(Duration::from_millis(50)).await;
+            Ok(7)
+        }))).await;
+
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        assert!(results.iter().all(|result| *result == Ok(7)));
+        assert!(lock(&cache.in_flight).is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn failed_loads_are_shared_but_not_cached() {
+        let cache: SharedCache<(bool, i64), i64, &str> = SharedCache::new(settings(600, 100));
+        let loads = AtomicUsize::new(0);
+
+        let results = join_all((0..5).map(|_| cache.get_or_load((true, 1), || async {
+            loads.fetch_add(1, Ordering::SeqCst);
+            time::sleep(Duration::from_millis(50)).await;
+            Err("unavailable")
+        }))).await;
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        assert!(results.iter().all(|result| *result == Err("unavailable")));
+
+        let retried = cache.get_or_load((true, 1), || async { Ok(7) }).await;
+        assert_eq!(retried, Ok(7));
+    }
+}
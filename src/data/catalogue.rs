@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use rocket::request::FromParam;
+use rocket::{Route, State};
+use rocket::serde::{Deserialize, Serialize, json::Json};
+
+use super::dlc::Dlc;
+use super::error::ApiError;
+use super::{Cache, LazyDbConn};
+
+/// Journal name, display name and category of every commodity known to the api.
+const COMMODITIES: &[(&str, &str, &str)] = &[
+    ("agronomictreatment", "Agronomic Treatment", "Chemicals"),
+    ("explosives", "Explosives", "Chemicals"),
+    ("hydrogenfuel", "Hydrogen Fuel", "Chemicals"),
+    ("hydrogenperoxide", "Hydrogen Peroxide", "Chemicals"),
+    ("liquidoxygen", "Liquid Oxygen", "Chemicals"),
+    ("mineraloil", "Mineral Oil", "Chemicals"),
+    ("nerveagents", "Nerve Agents", "Chemicals"),
+    ("pesticides", "Pesticides", "Chemicals"),
+    ("rockforthfertiliser", "Rockforth Fertiliser", "Chemicals"),
+    ("surfacestabilisers", "Surface Stabilisers", "Chemicals"),
+    ("syntheticreagents", "Synthetic Reagents", "Chemicals"),
+    ("tritium", "Tritium", "Chemicals"),
+    ("water", "Water", "Chemicals"),
+    ("clothing", "Clothing", "Consumer Items"),
+    ("consumertechnology", "Consumer Technology", "Consumer Items"),
+    ("domesticappliances", "Domestic Appliances", "Consumer Items"),
+    ("evacuationshelter", "Evacuation Shelter", "Consumer Items"),
+    ("survivalequipment", "Survival Equipment", "Consumer Items"),
+    ("algae", "Algae", "Foods"),
+    ("animalmeat", "Animal Meat", "Foods"),
+    ("coffee", "Coffee", "Foods"),
+    ("fish", "Fish", "Foods"),
+    ("foodcartridges", "Food Cartridges", "Foods"),
+    ("fruitandvegetables", "Fruit and Vegetables", "Foods"),
+    ("grain", "Grain", "Foods"),
+    ("syntheticmeat", "Synthetic Meat", "Foods"),
+    ("tea", "Tea", "Foods"),
+    ("ceramiccomposites", "Ceramic Composites", "Industrial Materials"),
+    ("cmmcomposite", "CMM Composite", "Industrial Materials"),
+    ("coolinghoses", "Micro-weave Cooling Hoses", "Industrial Materials"),
+    ("insulatingmembrane", "Insulating Membrane", "Industrial Materials"),
+    ("metaalloys", "Meta-Alloys", "Industrial Materials"),
+    ("neofabricinsulation", "Neofabric Insulation", "Industrial Materials"),
+    ("polymers", "Polymers", "Industrial Materials"),
+    ("semiconductors", "Semiconductors", "Industrial Materials"),
+    ("superconductors", "Superconductors", "Industrial Materials"),
+    ("beer", "Beer", "Legal Drugs"),
+    ("bootleggedliquor", "Bootlegged Liquor", "Legal Drugs"),
+    ("liquor", "Liquor", "Legal Drugs"),
+    ("narcotics", "Narcotics", "Legal Drugs"),
+    ("tobacco", "Tobacco", "Legal Drugs"),
+    ("wine", "Wine", "Legal Drugs"),
+    ("articulationmotors", "Articulation Motors", "Machinery"),
+    ("atmosphericextractors", "Atmospheric Processors", "Machinery"),
+    ("buildingfabricators", "Building Fabricators", "Machinery"),
+    ("cropharvesters", "Crop Harvesters", "Machinery"),
+    ("emergencypowercells", "Emergency Power Cells", "Machinery"),
+    ("exhaustmanifold", "Exhaust Manifold", "Machinery"),
+    ("geologicalequipment", "Geological Equipment", "Machinery"),
+    ("heatsinkinterlink", "Heatsink Interlink", "Machinery"),
+    ("hnshockmount", "HN Shock Mount", "Machinery"),
+    ("iondistributor", "Ion Distributor", "Machinery"),
+    ("magneticemittercoil", "Magnetic Emitter Coil", "Machinery"),
+    ("marineequipment", "Marine Equipment", "Machinery"),
+    ("microcontrollers", "Micro Controllers", "Machinery"),
+    ("mineralextractors", "Mineral Extractors", "Machinery"),
+    ("modularterminals", "Modular Terminals", "Machinery"),
+    ("powerconverter", "Power Converter", "Machinery"),
+    ("powergenerators", "Power Generators", "Machinery"),
+    ("powergridassembly", "Energy Grid Assembly", "Machinery"),
+    ("powertransferconduits", "Power Transfer Bus", "Machinery"),
+    ("radiationbaffle", "Radiation Baffle", "Machinery"),
+    ("reinforcedmountingplate", "Reinforced Mounting Plate", "Machinery"),
+    ("skimercomponents", "Skimmer Components", "Machinery"),
+    ("thermalcoolingunits", "Thermal Cooling Units", "Machinery"),
+    ("waterpurifiers", "Water Purifiers", "Machinery"),
+    ("advancedmedicines", "Advanced Medicines", "Medicines"),
+    ("agriculturalmedicines", "Agri-Medicines", "Medicines"),
+    ("basicmedicines", "Basic Medicines", "Medicines"),
+    ("combatstabilisers", "Combat Stabilisers", "Medicines"),
+    ("performanceenhancers", "Performance Enhancers", "Medicines"),
+    ("progenitorcells", "Progenitor Cells", "Medicines"),
+    ("aluminium", "Aluminium", "Metals"),
+    ("beryllium", "Beryllium", "Metals"),
+    ("bismuth", "Bismuth", "Metals"),
+    ("cobalt", "Cobalt", "Metals"),
+    ("copper", "Copper", "Metals"),
+    ("gallium", "Gallium", "Metals"),
+    ("gold", "Gold", "Metals"),
+    ("hafnium178", "Hafnium 178", "Metals"),
+    ("indium", "Indium", "Metals"),
+    ("lanthanum", "Lanthanum", "Metals"),
+    ("lithium", "Lithium", "Metals"),
+    ("osmium", "Osmium", "Metals"),
+    ("palladium", "Palladium", "Metals"),
+    ("platinum", "Platinum", "Metals"),
+    ("platinumaloy", "Platinum Alloy", "Metals"),
+    ("praseodymium", "Praseodymium", "Metals"),
+    ("samarium", "Samarium", "Metals"),
+    ("silver", "Silver", "Metals"),
+    ("steel", "Steel", "Metals"),
+    ("tantalum", "Tantalum", "Metals"),
+    ("thallium", "Thallium", "Metals"),
+    ("thorium", "Thorium", "Metals"),
+    ("titanium", "Titanium", "Metals"),
+    ("uranium", "Uranium", "Metals"),
+    ("alexandrite", "Alexandrite", "Minerals"),
+    ("bauxite", "Bauxite", "Minerals"),
+    ("benitoite", "Benitoite", "Minerals"),
+    ("bertrandite", "Bertrandite", "Minerals"),
+    ("bromellite", "Bromellite", "Minerals"),
+    ("coltan", "Coltan", "Minerals"),
+    ("cryolite", "Cryolite", "Minerals"),
+    ("gallite", "Gallite", "Minerals"),
+    ("goslarite", "Goslarite", "Minerals"),
+    ("grandidierite", "Grandidierite", "Minerals"),
+    ("indite", "Indite", "Minerals"),
+    ("jadeite", "Jadeite", "Minerals"),
+    ("lepidolite", "Lepidolite", "Minerals"),
+    ("lithiumhydroxide", "Lithium Hydroxide", "Minerals"),
+    ("lowtemperaturediamond", "Low Temperature Diamonds", "Minerals"),
+    ("methaneclathrate", "Methane Clathrate", "Minerals"),
+    ("methanolmonohydratecrystals", "Methanol Monohydrate Crystals", "Minerals"),
+    ("moissanite", "Moissanite", "Minerals"),
+    ("monazite", "Monazite", "Minerals"),
+    ("musgravite", "Musgravite", "Minerals"),
+    ("opal", "Void Opal", "Minerals"),
+    ("painite", "Painite", "Minerals"),
+    ("pyrophyllite", "Pyrophyllite", "Minerals"),
+    ("rhodplumsite", "Rhodplumsite", "Minerals"),
+    ("rutile", "Rutile", "Minerals"),
+    ("serendibite", "Serendibite", "Minerals"),
+    ("taaffeite", "Taaffeite", "Minerals"),
+    ("uraninite", "Uraninite", "Minerals"),
+    ("damagedescapepod", "Damaged Escape Pod", "Salvage"),
+    ("occupiedcryopod", "Occupied Escape Pod", "Salvage"),
+    ("usscargoblackbox", "Black Box", "Salvage"),
+    ("wreckagecomponents", "Wreckage Components", "Salvage"),
+    ("imperialslaves", "Imperial Slaves", "Slavery"),
+    ("slaves", "Slaves", "Slavery"),
+    ("advancedcatalysers", "Advanced Catalysers", "Technology"),
+    ("animalmonitors", "Animal Monitors", "Technology"),
+    ("aquaponicsystems", "Aquaponic Systems", "Technology"),
+    ("autofabricators", "Auto-Fabricators", "Technology"),
+    ("bioreducinglichen", "Bioreducing Lichen", "Technology"),
+    ("computercomponents", "Computer Components", "Technology"),
+    ("hazardousenvironmentsuits", "H.E. Suits", "Technology"),
+    ("medicaldiagnosticequipment", "Medical Diagnostic Equipment", "Technology"),
+    ("microbialfurnaces", "Microbial Furnaces", "Technology"),
+    ("mutomimager", "Muon Imager", "Technology"),
+    ("nanobreakers", "Nanobreakers", "Technology"),
+    ("resonatingseparators", "Resonating Separators", "Technology"),
+    ("robotics", "Robotics", "Technology"),
+    ("structuralregulators", "Structural Regulators", "Technology"),
+    ("telemetrysuite", "Telemetry Suite", "Technology"),
+    ("terrainenrichmentsystems", "Land Enrichment Systems", "Technology"),
+    ("conductivefabrics", "Conductive Fabrics", "Textiles"),
+    ("leather", "Leather", "Textiles"),
+    ("militarygradefabrics", "Military Grade Fabrics", "Textiles"),
+    ("naturalfabrics", "Natural Fabrics", "Textiles"),
+    ("syntheticfabrics", "Synthetic Fabrics", "Textiles"),
+    ("biowaste", "Biowaste", "Waste"),
+    ("chemicalwaste", "Chemical Waste", "Waste"),
+    ("scrap", "Scrap", "Waste"),
+    ("toxicwaste", "Toxic Waste", "Waste"),
+    ("battleweapons", "Battle Weapons", "Weapons"),
+    ("landmines", "Landmines", "Weapons"),
+    ("nonlethalweapons", "Non-Lethal Weapons", "Weapons"),
+    ("personalweapons", "Personal Weapons", "Weapons"),
+    ("reactivearmour", "Reactive Armour", "Weapons"),
+];
+
+/// Commodity name as stored in the database, parsed from any of the names a client may know.
+///
+/// `Gold`, `gold`, `$gold_name;` and display names like `Low Temperature Diamonds` all resolve to
+/// the lowercase journal name. Unknown names are only lowercased and stripped of everything but letters and digits.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CommodityName(pub String);
+
+impl CommodityName {
+    pub fn normalize(name: &str) -> Self {
+        let mut name = name.trim().to_lowercase();
+        if let Some(symbol) = name.strip_prefix('$') {
+            name = symbol.trim_end_matches(';').trim_end_matches("_name").to_string();
+        }
+        let compact: String = name.chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+
+        let journal_name = COMMODITIES.iter()
+            .find(|(journal_name, display_name, _)| *journal_name == compact || compact_name(display_name) == compact)
+            .map(|(journal_name, _, _)| journal_name.to_string());
+        CommodityName(journal_name.unwrap_or(compact))
+    }
+}
+
+impl<'a> FromParam<'a> for CommodityName {
+    type Error = ApiError;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        let name = CommodityName::normalize(param);
+        if name.0.is_empty() {
+            return Err(ApiError::BadRequest(format!("Invalid commodity name '{}'", param)));
+        }
+        Ok(name)
+    }
+}
+
+fn compact_name(name: &str) -> String {
+    name.chars().filter(|c| c.is_ascii_alphanumeric()).map(|c| c.to_ascii_lowercase()).collect()
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CatalogueEntry {
+    /// Journal name accepted by every commodity route
+    pub name: String,
+    /// Symbol used in the journal, e.g. `$gold_name;`
+    pub symbol: String,
+    /// English display name
+    pub display_name: Option<String>,
+    pub category: Option<String>,
+    /// Galaxy averages over all markets buying or selling the commodity
+    pub buy_price: Option<i32>,
+    pub sell_price: Option<i32>,
+    pub mean_price: Option<i32>,
+    /// Number of markets trading the commodity
+    pub markets: i64,
+}
+
+/// Every known commodity, including the ones seen on markets but missing from [`COMMODITIES`].
+#[get("/<dlc>/commodities")]
+async fn commodities(cache: &State<Arc<Cache>>, db: LazyDbConn<'_>, dlc: Result<Dlc, ApiError>) -> Result<Json<Vec<CatalogueEntry>>, ApiError> {
+    let odyssey = dlc?.odyssey();
+    let catalogue = cache.commodities.get_or_load((odyssey, ()), || async move {
+        let db = db.get().await?;
+        db.run(move |conn| {
+            //language=postgresql
+            let sql = "select name,
+                    cast(avg(buy_price) filter (where buy_price > 0) as integer),
+                    cast(avg(sell_price) filter (where sell_price > 0) as integer),
+                    cast(avg(mean_price) as integer),
+                    count(distinct market_id)
+                from commodity where odyssey = $1 group by name";
+
+            let mut entries: HashMap<String, CatalogueEntry> = COMMODITIES.iter()
+                .map(|(name, display_name, category)| (name.to_string(), CatalogueEntry {
+                    name: name.to_string(),
+                    symbol: format!("${}_name;", name),
+                    display_name: Some(display_name.to_string()),
+                    category: Some(category.to_string()),
+                    buy_price: None,
+                    sell_price: None,
+                    mean_price: None,
+                    markets: 0,
+                }))
+                .collect();
+
+            for row in conn.query(sql, &[&odyssey])? {
+                let name: String = row.try_get(0)?;
+                let entry = entries.entry(name.clone()).or_insert_with(|| CatalogueEntry {
+                    symbol: format!("${}_name;", name),
+                    name,
+                    display_name: None,
+                    category: None,
+                    buy_price: None,
+                    sell_price: None,
+                    mean_price: None,
+                    markets: 0,
+                });
+                entry.buy_price = row.try_get(1)?;
+                entry.sell_price = row.try_get(2)?;
+                entry.mean_price = row.try_get(3)?;
+                entry.markets = row.try_get(4)?;
+            }
+
+            let mut catalogue: Vec<CatalogueEntry> = entries.into_values().collect();
+            //Known categories first, commodities only seen on markets last
+            catalogue.sort_by(|a, b| a.category.is_none().cmp(&b.category.is_none())
+                .then(a.category.cmp(&b.category))
+                .then(a.name.cmp(&b.name)));
+            Ok(catalogue)
+        }).await
+    }).await;
+    catalogue.map(Json)
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![commodities]
+}
@@ -0,0 +1,55 @@
+use std::fmt::{Display, Formatter};
+
+use rocket::request::FromParam;
+use rocket::serde::Serialize;
+
+use super::error::ApiError;
+
+/// Game version selected by the `<dlc>` path segment.
+///
+/// The database only distinguishes Odyssey from Horizons data. The live galaxy is the one
+/// Odyssey clients play in, legacy is the old Horizons (3.8) galaxy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Dlc {
+    Odyssey,
+    Horizons,
+    Legacy,
+    Live,
+}
+
+impl Dlc {
+    /// Value of the `odyssey` column holding this dlc's data
+    pub fn odyssey(self) -> bool {
+        match self {
+            Dlc::Odyssey | Dlc::Live => true,
+            Dlc::Horizons | Dlc::Legacy => false,
+        }
+    }
+}
+
+impl Display for Dlc {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Dlc::Odyssey => "odyssey",
+            Dlc::Horizons => "horizons",
+            Dlc::Legacy => "legacy",
+            Dlc::Live => "live",
+        };
+        f.write_str(name)
+    }
+}
+
+impl<'a> FromParam<'a> for Dlc {
+    type Error = ApiError;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        match param.to_ascii_lowercase().as_str() {
+            "odyssey" => Ok(Dlc::Odyssey),
+            "horizons" => Ok(Dlc::Horizons),
+            "legacy" => Ok(Dlc::Legacy),
+            "live" => Ok(Dlc::Live),
+            _ => Err(ApiError::BadRequest(format!("Unknown dlc '{}', expected one of odyssey, horizons, legacy or live", param))),
+        }
+    }
+}
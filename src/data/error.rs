@@ -0,0 +1,133 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use rocket::form;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::serde::json::Json;
+use rocket::serde::Serialize;
+use rocket_sync_db_pools::postgres;
+
+/// Error returned by every data route, rendered as an [`ErrorBody`] with a matching status code.
+#[derive(Debug, Clone)]
+pub enum ApiError {
+    /// The requested resource does not exist
+    NotFound(String),
+    /// The request itself is malformed, e.g. an unknown dlc or an invalid parameter
+    BadRequest(String),
+    /// The database could not be reached or failed to answer the query
+    Database(String),
+    /// Anything else that went wrong on our side
+    Internal(String),
+}
+
+/// JSON body of every error response.
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ErrorBody {
+    pub status: u16,
+    pub error: &'static str,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn status(&self) -> Status {
+        match self {
+            ApiError::NotFound(_) => Status::NotFound,
+            ApiError::BadRequest(_) => Status::BadRequest,
+            ApiError::Database(_) => Status::ServiceUnavailable,
+            ApiError::Internal(_) => Status::InternalServerError,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) => "not_found",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Database(_) => "database",
+            ApiError::Internal(_) => "internal",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            ApiError::NotFound(message)
+            | ApiError::BadRequest(message)
+            | ApiError::Database(message)
+            | ApiError::Internal(message) => message,
+        }
+    }
+
+    /// Maps a status produced outside of a route (e.g. by a failing guard) to an error.
+    pub fn from_status(status: Status) -> Self {
+        let message = status.reason_lossy().to_string();
+        match status.code {
+            404 => ApiError::NotFound(message),
+            400 | 422 => ApiError::BadRequest(message),
+            503 => ApiError::Database(message),
+            _ => ApiError::Internal(message),
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody {
+            status: self.status().code,
+            error: self.kind(),
+            message: self.message().to_string(),
+        }
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.kind(), self.message())
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Only connection problems count as the database being unavailable. Failing statements and rows not
+/// matching the expected types are bugs on our side. The details are logged, never sent to the client.
+impl From<postgres::Error> for ApiError {
+    fn from(error: postgres::Error) -> Self {
+        error!("Database error: {}", error);
+        let unavailable = error.is_closed()
+            || error.source().is_some_and(|source| source.is::<std::io::Error>())
+            //Connection exceptions, insufficient resources and operator intervention like a shutdown or a cancelled query
+            || error.code().is_some_and(|code| matches!(&code.code()[..2], "08" | "53" | "57"));
+        if unavailable {
+            ApiError::Database("The database is not available".to_string())
+        } else {
+            ApiError::Internal("Failed to query the database".to_string())
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status();
+        if let ApiError::Database(_) | ApiError::Internal(_) = self {
+            error!("{} {}: {}", request.method(), request.uri(), self);
+        }
+        (status, Json(self.body())).respond_to(request)
+    }
+}
+
+/// Reads a query parameter taken as a `form::Result`, which unlike an `Option` does not swallow invalid values.
+/// Missing parameters are `None`, invalid ones a bad request naming the parameter.
+pub fn query_param<T>(name: &str, value: form::Result<'_, T>) -> Result<Option<T>, ApiError> {
+    match value {
+        Ok(value) => Ok(Some(value)),
+        Err(errors) if errors.iter().all(|error| matches!(error.kind, form::error::ErrorKind::Missing)) => Ok(None),
+        Err(errors) => {
+            let reasons: Vec<String> = errors.iter().map(|error| error.kind.to_string()).collect();
+            Err(ApiError::BadRequest(format!("Invalid parameter {}: {}", name, reasons.join(", "))))
+        }
+    }
+}
+
+#[catch(default)]
+pub fn default_catcher(status: Status, _request: &Request) -> ApiError {
+    ApiError::from_status(status)
+}
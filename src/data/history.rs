@@ -0,0 +1,367 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocket::form;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Request, Route, State};
+use rocket::serde::{Deserialize, Serialize, json::Json};
+
+use super::catalogue::CommodityName;
+use super::dlc::Dlc;
+use super::error::{query_param, ApiError};
+use super::{page_bounds, Cache, LazyDbConn};
+
+/// Candles returned if `from` is not given
+const DEFAULT_CANDLES: i64 = 100;
+/// Upper bound of candles per request, so a tiny interval over a long range cannot produce huge responses
+const MAX_CANDLES: i64 = 5000;
+/// Longest window the movers can be computed over, 30 days
+const MAX_MOVER_WINDOW: i64 = 30 * 86400;
+
+/// Commodity name, interval width, from and to of a candle request
+pub type CandleKey = (String, i64, i64, i64);
+
+/// Width of a candle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromFormField, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Interval {
+    Hour,
+    Day,
+    Week,
+}
+
+impl Interval {
+    fn seconds(self) -> i64 {
+        match self {
+            Interval::Hour => 3600,
+            Interval::Day => 86400,
+            Interval::Week => 604800,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Ohlc {
+    pub open: i32,
+    pub high: i32,
+    pub low: i32,
+    pub close: i32,
+    pub average: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Candle {
+    /// Start of the bucket
+    pub timestamp: i64,
+    pub buy_price: Ohlc,
+    pub sell_price: Ohlc,
+    pub mean_price: Ohlc,
+    /// History rows aggregated into this candle
+    pub samples: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CommodityCandles {
+    pub name: String,
+    pub odyssey: bool,
+    pub interval: Interval,
+    pub from: i64,
+    pub to: i64,
+    /// Oldest first. Buckets without any history row are left out.
+    pub candles: Vec<Candle>,
+}
+
+/// Request guard forwarding requests without any candle parameter to the raw commodity history.
+pub struct CandleQuery;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CandleQuery {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        if request.query_fields().any(|field| matches!(field.name.key_lossy().as_str(), "interval" | "from" | "to")) {
+            Outcome::Success(CandleQuery)
+        } else {
+            Outcome::Forward(Status::NotFound)
+        }
+    }
+}
+
+/// Commodity history aggregated into candles between the unix timestamps `from` (inclusive) and `to` (exclusive).
+///
+/// Both bounds are aligned to the interval. `to` defaults to the end of the current bucket
+/// and `from` to [`DEFAULT_CANDLES`] buckets before `to`. Without any of the three parameters
+/// the raw history is returned instead, giving only `from` or `to` without an `interval` is a bad request.
+#[get("/<dlc>/commodity_history/<name>?<interval>&<from>&<to>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn candles(_query: CandleQuery, cache: &State<Arc<Cache>>, db: LazyDbConn<'_>, dlc: Result<Dlc, ApiError>, name: CommodityName,
+                     interval: form::Result<'_, Interval>, from: form::Result<'_, i64>, to: form::Result<'_, i64>) -> Result<Json<CommodityCandles>, ApiError> {
+    let odyssey = dlc?.odyssey();
+    let interval = query_param("interval", interval)?
+        .ok_or_else(|| ApiError::BadRequest("Parameter interval is required with from or to".to_string()))?;
+    let (from, to) = (query_param("from", from)?, query_param("to", to)?);
+    let width = interval.seconds();
+    let (from, to) = candle_range(from, to, width, now())?;
+
+    let name = name.0;
+    let key = (odyssey, (name.clone(), width, from, to));
+    let candles = cache.commodity_candles.get_or_load(key, || async move {
+        let db = db.get().await?;
+        db.run(move |conn| {
+            //language=postgresql
+            let sql = "select (timestamp / $3) * $3 as bucket,
+                    (array_agg(buy_price order by timestamp))[1], max(buy_price), min(buy_price),
+                    (array_agg(buy_price order by timestamp desc))[1], avg(buy_price)::float8,
+                    (array_agg(sell_price order by timestamp))[1], max(sell_price), min(sell_price),
+                    (array_agg(sell_price order by timestamp desc))[1], avg(sell_price)::float8,
+                    (array_agg(mean_price order by timestamp))[1], max(mean_price), min(mean_price),
+                    (array_agg(mean_price order by timestamp desc))[1], avg(mean_price)::float8,
+                    count(*)
+                from commodity_history
+                where odyssey = $1 and name = $2 and timestamp >= $4 and timestamp < $5
+                group by bucket
+                order by bucket";
+            let rows = conn.query(sql, &[&odyssey, &name, &width, &from, &to])?;
+
+            let mut candles = vec![];
+            for row in rows {
+                let ohlc = |start: usize| -> Result<Ohlc, ApiError> {
+                    Ok(Ohlc {
+                        open: row.try_get(start)?,
+                        high: row.try_get(start + 1)?,
+                        low: row.try_get(start + 2)?,
+                        close: row.try_get(start + 3)?,
+                        average: row.try_get(start + 4)?,
+                    })
+                };
+                candles.push(Candle {
+                    timestamp: row.try_get(0)?,
+                    buy_price: ohlc(1)?,
+                    sell_price: ohlc(6)?,
+                    mean_price: ohlc(11)?,
+                    samples: row.try_get(16)?,
+                });
+            }
+            Ok(CommodityCandles { name, odyssey, interval, from, to, candles })
+        }).await
+    }).await;
+    candles.map(Json)
+}
+
+/// Price a mover is ranked by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+pub enum MoverPrice {
+    #[field(value = "buy")]
+    Buy,
+    #[field(value = "sell")]
+    Sell,
+    #[field(value = "mean")]
+    Mean,
+}
+
+/// Whether movers are ranked by the change in credits or in percent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+pub enum MoverRank {
+    #[field(value = "absolute")]
+    Absolute,
+    #[field(value = "percent")]
+    Percent,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PriceChange {
+    /// First price inside the window
+    pub from: i32,
+    /// Latest price
+    pub to: i32,
+    pub change: i32,
+    /// Change relative to `from`, missing if `from` is 0
+    pub percent: Option<f64>,
+}
+
+impl PriceChange {
+    fn new(from: i32, to: i32) -> Self {
+        let change = to - from;
+        PriceChange {
+            from,
+            to,
+            change,
+            percent: (from != 0).then(|| change as f64 * 100.0 / from as f64),
+        }
+    }
+
+    /// Size of the change for ranking, ignoring its direction
+    fn magnitude(&self, rank: MoverRank) -> f64 {
+        match rank {
+            MoverRank::Absolute => (self.change as f64).abs(),
+            MoverRank::Percent => self.percent.map_or(0.0, f64::abs),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Mover {
+    pub name: String,
+    /// Unix timestamps of the first and last history row inside the window
+    pub first_seen: i64,
+    pub last_seen: i64,
+    pub samples: i64,
+    pub buy_price: PriceChange,
+    pub sell_price: PriceChange,
+    pub mean_price: PriceChange,
+}
+
+impl Mover {
+    fn price(&self, price: MoverPrice) -> &PriceChange {
+        match price {
+            MoverPrice::Buy => &self.buy_price,
+            MoverPrice::Sell => &self.sell_price,
+            MoverPrice::Mean => &self.mean_price,
+        }
+    }
+}
+
+/// Commodities whose price changed the most within `window`, biggest change first regardless of its direction.
+///
+/// `window` is a duration like `90m`, `24h`, `7d` or `2w` and defaults to `24h`. The change is measured between
+/// the first and the last history row inside the window, so commodities with a single row are left out.
+#[get("/<dlc>/commodities/movers?<window>&<price>&<rank>&<limit>")]
+pub async fn movers(cache: &State<Arc<Cache>>, db: LazyDbConn<'_>, dlc: Result<Dlc, ApiError>, window: Option<&str>, price: form::Result<'_, MoverPrice>,
+                    rank: form::Result<'_, MoverRank>, limit: form::Result<'_, i64>) -> Result<Json<Vec<Mover>>, ApiError> {
+    let odyssey = dlc?.odyssey();
+    let window = window.map_or(Ok(86400), parse_window)?;
+    let price = query_param("price", price)?.unwrap_or(MoverPrice::Sell);
+    let rank = query_param("rank", rank)?.unwrap_or(MoverRank::Percent);
+    let (limit, _) = page_bounds(query_param("limit", limit)?, None)?;
+
+    let movers = cache.commodity_movers.get_or_load((odyssey, window), || async move {
+        let db = db.get().await?;
+        db.run(move |conn| {
+            //language=postgresql
+            let sql = "select name, min(timestamp), max(timestamp), count(*),
+                    (array_agg(buy_price order by timestamp))[1], (array_agg(buy_price order by timestamp desc))[1],
+                    (array_agg(sell_price order by timestamp))[1], (array_agg(sell_price order by timestamp desc))[1],
+                    (array_agg(mean_price order by timestamp))[1], (array_agg(mean_price order by timestamp desc))[1]
+                from commodity_history
+                where odyssey = $1 and timestamp >= $2
+                group by name
+                having count(*) > 1";
+            let rows = conn.query(sql, &[&odyssey, &(now() - window)])?;
+
+            let mut movers = vec![];
+            for row in rows {
+                movers.push(Mover {
+                    name: row.try_get(0)?,
+                    first_seen: row.try_get(1)?,
+                    last_seen: row.try_get(2)?,
+                    samples: row.try_get(3)?,
+                    buy_price: PriceChange::new(row.try_get(4)?, row.try_get(5)?),
+                    sell_price: PriceChange::new(row.try_get(6)?, row.try_get(7)?),
+                    mean_price: PriceChange::new(row.try_get(8)?, row.try_get(9)?),
+                });
+            }
+            Ok(movers)
+        }).await
+    }).await?;
+
+    let mut movers = movers;
+    movers.sort_by(|a, b| b.price(price).magnitude(rank).total_cmp(&a.price(price).magnitude(rank))
+        .then_with(|| a.name.cmp(&b.name)));
+    movers.truncate(limit as usize);
+    Ok(Json(movers))
+}
+
+/// Parses durations like `24h` into seconds.
+fn parse_window(window: &str) -> Result<i64, ApiError> {
+    let invalid = || ApiError::BadRequest(format!("Invalid window '{}', expected a duration like 24h or 7d", window));
+    let window = window.trim();
+    let split = window.len().checked_sub(1).filter(|&split| window.is_char_boundary(split)).ok_or_else(invalid)?;
+    let (amount, unit) = window.split_at(split);
+    let unit = match unit {
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 604800,
+        _ => return Err(invalid()),
+    };
+    let seconds = amount.parse::<i64>().map_err(|_| invalid())?.checked_mul(unit).ok_or_else(invalid)?;
+    if !(seconds > 0 && seconds <= MAX_MOVER_WINDOW) {
+        return Err(ApiError::BadRequest(format!("Parameter window must be greater than 0 and at most {}d", MAX_MOVER_WINDOW / 86400)));
+    }
+    Ok(seconds)
+}
+
+/// Current unix timestamp in seconds
+pub fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs() as i64)
+}
+
+/// Aligned bounds of a candle request, see [`candles`].
+///
+/// Timestamps have to lie between 0 and one interval after `now`, which also keeps the arithmetic from overflowing.
+fn candle_range(from: Option<i64>, to: Option<i64>, width: i64, now: i64) -> Result<(i64, i64), ApiError> {
+    let latest = now.saturating_add(width);
+    if from.into_iter().chain(to).any(|timestamp| !(0..=latest).contains(&timestamp)) {
+        return Err(ApiError::BadRequest(format!("Parameters from and to must be unix timestamps between 0 and {}", latest)));
+    }
+    let overflow = || ApiError::BadRequest("Parameters from and to are out of range".to_string());
+    let to = align_up(to.unwrap_or(now), width).ok_or_else(overflow)?;
+    let from = match from {
+        Some(from) => from,
+        None => to.checked_sub(DEFAULT_CANDLES * width).ok_or_else(overflow)?.max(0),
+    }.div_euclid(width) * width;
+    if from >= to {
+        return Err(ApiError::BadRequest("Parameter from must be before to".to_string()));
+    }
+    if (to - from) / width > MAX_CANDLES {
+        return Err(ApiError::BadRequest(format!("At most {} candles can be requested at once, use a larger interval or a shorter range", MAX_CANDLES)));
+    }
+    Ok((from, to))
+}
+
+fn align_up(timestamp: i64, width: i64) -> Option<i64> {
+    Some(timestamp.checked_add(width - 1)?.div_euclid(width) * width)
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![candles, movers]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: i64 = 3600;
+    const NOW: i64 = 1_700_000_000;
+
+    #[test]
+    fn range_is_aligned_to_the_interval() {
+        assert_eq!(candle_range(Some(HOUR + 1), Some(3 * HOUR - 1), HOUR, NOW).unwrap(), (HOUR, 3 * HOUR));
+        let (from, to) = candle_range(None, None, HOUR, NOW).unwrap();
+        assert_eq!(to, NOW.div_euclid(HOUR) * HOUR + HOUR);
+        assert_eq!(from, to - DEFAULT_CANDLES * HOUR);
+    }
+
+    #[test]
+    fn timestamps_outside_the_bounds_are_rejected() {
+        for (from, to) in [(None, Some(i64::MAX)), (Some(i64::MIN), Some(9_000_000_000_000_000_000)), (Some(-1), None),
+                           (Some(0), Some(NOW + 2 * HOUR)), (Some(i64::MAX), None)] {
+            assert!(matches!(candle_range(from, to, HOUR, NOW), Err(ApiError::BadRequest(_))), "{:?} {:?}", from, to);
+        }
+        assert!(candle_range(Some(0), Some(NOW + HOUR), Interval::Week.seconds(), NOW).is_ok());
+    }
+
+    #[test]
+    fn empty_and_oversized_ranges_are_rejected() {
+        assert!(candle_range(Some(2 * HOUR), Some(HOUR), HOUR, NOW).is_err());
+        assert!(candle_range(Some(0), Some(NOW), HOUR, NOW).is_err());
+        assert_eq!(candle_range(None, Some(HOUR), HOUR, NOW).unwrap(), (0, HOUR));
+    }
+}
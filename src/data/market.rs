@@ -0,0 +1,297 @@
+use rocket::{form, Route};
+use rocket::serde::{Deserialize, Serialize, json::Json};
+use rocket_sync_db_pools::postgres;
+
+use super::catalogue::CommodityName;
+use super::dlc::Dlc;
+use super::error::{query_param, ApiError};
+use super::systems::{check_radius, distance_sql, load_coordinates, within_radius_sql, Coordinates};
+use super::{page_bounds, LazyDbConn};
+
+/// Radius of the nearest market search if none is given
+const DEFAULT_NEAREST_RADIUS: f64 = 50.0;
+/// Credits per ton a light year of travel is worth when scoring the nearest markets
+const DEFAULT_CREDITS_PER_LY: f64 = 50.0;
+
+/// Column the market listing of a commodity is sorted by.
+///
+/// Buy prices are sorted cheapest first, everything else highest first.
+/// Sorting by a price only lists the stations that actually buy or sell the commodity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+pub enum MarketSort {
+    #[field(value = "buy_price")]
+    BuyPrice,
+    #[field(value = "sell_price")]
+    SellPrice,
+    #[field(value = "stock")]
+    Stock,
+    #[field(value = "demand")]
+    Demand,
+}
+
+impl MarketSort {
+    fn column(self) -> &'static str {
+        match self {
+            MarketSort::BuyPrice => "c.buy_price",
+            MarketSort::SellPrice => "c.sell_price",
+            MarketSort::Stock => "c.stock",
+            MarketSort::Demand => "c.demand",
+        }
+    }
+
+    /// Name of the sort as in the query
+    fn name(self) -> &'static str {
+        match self {
+            MarketSort::BuyPrice => "buy_price",
+            MarketSort::SellPrice => "sell_price",
+            MarketSort::Stock => "stock",
+            MarketSort::Demand => "demand",
+        }
+    }
+
+    fn ascending(self) -> bool {
+        self == MarketSort::BuyPrice
+    }
+
+    fn value(self, market: &CommodityMarket) -> i32 {
+        match self {
+            MarketSort::BuyPrice => market.buy_price,
+            MarketSort::SellPrice => market.sell_price,
+            MarketSort::Stock => market.stock,
+            MarketSort::Demand => market.demand,
+        }
+    }
+}
+
+/// Position in a sorted market listing: the sort, its value and the market id of the last result.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Cursor {
+    sort: MarketSort,
+    value: i32,
+    market_id: i64,
+}
+
+impl Cursor {
+    /// Parses `<sort>_<value>_<market id>`, which has to be a cursor of the listing sorted by `sort`.
+    fn parse(cursor: &str, sort: MarketSort) -> Result<Self, ApiError> {
+        let invalid = || ApiError::BadRequest(format!("Invalid cursor '{}'", cursor));
+        let mut parts = cursor.rsplitn(3, '_');
+        let (Some(market_id), Some(value), Some(name)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid());
+        };
+        if name != sort.name() {
+            return Err(ApiError::BadRequest(format!("Cursor '{}' does not belong to sort {}", cursor, sort.name())));
+        }
+        Ok(Cursor {
+            sort,
+            value: value.parse().map_err(|_| invalid())?,
+            market_id: market_id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl std::fmt::Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}_{}_{}", self.sort.name(), self.value, self.market_id)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CommodityMarket {
+    pub market_id: i64,
+    pub station: Option<String>,
+    pub system: Option<String>,
+    pub system_address: Option<i64>,
+    pub buy_price: i32,
+    pub sell_price: i32,
+    pub mean_price: i32,
+    pub stock: i32,
+    pub demand: i32,
+    pub carrier: bool,
+    /// Light years to the system given by `from`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance: Option<f64>,
+}
+
+/// Whether the client wants to buy the commodity at a station or sell it there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+pub enum MarketMode {
+    #[field(value = "buy")]
+    Buy,
+    #[field(value = "sell")]
+    Sell,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NearestMarket {
+    #[serde(flatten)]
+    pub market: CommodityMarket,
+    /// Price adjusted by the travel distance, see [`nearest`]
+    pub score: f64,
+}
+
+/// Page of a cursor paginated listing. Pass `next_cursor` as `cursor` to get the next page.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CursorPage<T> {
+    pub results: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+/// Every station trading the commodity.
+#[get("/<dlc>/commodity/<name>/markets?<sort>&<min_stock>&<min_demand>&<from>&<cursor>&<limit>&<include_carriers>")]
+#[allow(clippy::too_many_arguments)]
+async fn markets(db: LazyDbConn<'_>, dlc: Result<Dlc, ApiError>, name: CommodityName, sort: form::Result<'_, MarketSort>,
+                 min_stock: form::Result<'_, i32>, min_demand: form::Result<'_, i32>, from: form::Result<'_, i64>, cursor: Option<&str>,
+                 limit: form::Result<'_, i64>, include_carriers: form::Result<'_, bool>) -> Result<Json<CursorPage<CommodityMarket>>, ApiError> {
+    let odyssey = dlc?.odyssey();
+    let sort = query_param("sort", sort)?.unwrap_or(MarketSort::SellPrice);
+    let (min_stock, min_demand) = (query_param("min_stock", min_stock)?, query_param("min_demand", min_demand)?);
+    let from = query_param("from", from)?;
+    let include_carriers = query_param("include_carriers", include_carriers)?;
+    let cursor = cursor.map(|cursor| Cursor::parse(cursor, sort)).transpose()?;
+    let (limit, _) = page_bounds(query_param("limit", limit)?, None)?;
+
+    let db = db.get().await?;
+    let page = db.run(move |conn| {
+        let origin = from.map(|address| load_coordinates(conn, address, odyssey)).transpose()?;
+
+        let column = sort.column();
+        let (direction, after) = if sort.ascending() { ("asc", ">") } else { ("desc", "<") };
+        let price_filter = match sort {
+            MarketSort::BuyPrice => "and c.buy_price > 0",
+            MarketSort::SellPrice => "and c.sell_price > 0",
+            MarketSort::Stock | MarketSort::Demand => "",
+        };
+        //Sort column and direction come from the enum above, never from user input
+        //language=postgresql
+        let sql = format!("select {MARKET_COLUMNS}
+            from commodity c
+                inner join station s on s.market_id = c.market_id
+                left join system sy on sy.name = s.system_name and sy.odyssey = c.odyssey
+            where c.name = $1 and c.odyssey = $2
+              and ($8 or not s.carrier)
+              and coalesce(c.stock, 0) >= $3
+              and coalesce(c.demand, 0) >= $4
+              {price_filter}
+              and ($5::int is null or coalesce({column}, 0) {after} $5 or (coalesce({column}, 0) = $5 and c.market_id > $6))
+            order by coalesce({column}, 0) {direction}, c.market_id
+            limit $7");
+
+        let rows = conn.query(sql.as_str(), &[&name.0, &odyssey, &min_stock.unwrap_or(0), &min_demand.unwrap_or(0),
+            &cursor.map(|cursor| cursor.value), &cursor.map(|cursor| cursor.market_id).unwrap_or(0), &(limit + 1),
+            &include_carriers.unwrap_or(false)])?;
+
+        let mut results = vec![];
+        for row in rows {
+            let position = Coordinates::from_columns(row.try_get(9)?, row.try_get(10)?, row.try_get(11)?);
+            let distance = origin.zip(position).map(|(origin, position)| origin.distance(&position));
+            results.push(market_from_row(&row, distance)?);
+        }
+
+        let mut next_cursor = None;
+        if results.len() as i64 > limit {
+            results.truncate(limit as usize);
+            next_cursor = results.last().map(|last| Cursor { sort, value: sort.value(last), market_id: last.market_id }.to_string());
+        }
+        Ok::<_, ApiError>(CursorPage { results, next_cursor })
+    }).await?;
+    Ok(Json(page))
+}
+
+/// Stations within `radius` light years of the `from` system to buy or sell the commodity at, best first.
+///
+/// Each light year of distance is worth `credits_per_ly` credits per ton. Buying, the score is the buy price
+/// plus that travel cost and lower is better. Selling, it is the sell price minus the travel cost and higher is better.
+#[get("/<dlc>/commodity/<name>/nearest?<from>&<mode>&<radius>&<min_stock>&<min_demand>&<credits_per_ly>&<include_carriers>&<limit>")]
+#[allow(clippy::too_many_arguments)]
+async fn nearest(db: LazyDbConn<'_>, dlc: Result<Dlc, ApiError>, name: CommodityName, from: i64, mode: MarketMode, radius: Option<f64>,
+                 min_stock: Option<i32>, min_demand: Option<i32>, credits_per_ly: Option<f64>, include_carriers: Option<bool>,
+                 limit: Option<i64>) -> Result<Json<Vec<NearestMarket>>, ApiError> {
+    let odyssey = dlc?.odyssey();
+    let radius = check_radius(radius.unwrap_or(DEFAULT_NEAREST_RADIUS))?;
+    let credits_per_ly = credits_per_ly.unwrap_or(DEFAULT_CREDITS_PER_LY);
+    if !(credits_per_ly >= 0.0 && credits_per_ly.is_finite()) {
+        return Err(ApiError::BadRequest("Parameter credits_per_ly must not be negative".to_string()));
+    }
+    let (limit, _) = page_bounds(limit, None)?;
+
+    let db = db.get().await?;
+    let results = db.run(move |conn| {
+        let origin = load_coordinates(conn, from, odyssey)?;
+        let (distance, within_radius) = (distance_sql(3), within_radius_sql(3, 6));
+        let score = format!("(case when $10 then coalesce(c.buy_price, 0) + {distance} * $11::float8
+            else coalesce(c.sell_price, 0) - {distance} * $11::float8 end)");
+        //Lower scores are better when buying, higher ones when selling
+        //language=postgresql
+        let sql = format!("select {MARKET_COLUMNS},{distance},{score}
+            from system sy
+                inner join station s on s.system_name = sy.name
+                inner join commodity c on c.market_id = s.market_id and c.odyssey = sy.odyssey
+            where c.name = $1 and sy.odyssey = $2
+              and {within_radius}
+              and ($9 or not s.carrier)
+              and (($10 and c.buy_price > 0 and coalesce(c.stock, 0) >= $7)
+                or (not $10 and c.sell_price > 0 and coalesce(c.demand, 0) >= $8))
+            order by case when $10 then {score} else -{score} end, c.market_id
+            limit $12");
+        let rows = conn.query(sql.as_str(), &[&name.0, &odyssey, &origin.x, &origin.y, &origin.z, &radius,
+            &min_stock.unwrap_or(1), &min_demand.unwrap_or(1), &include_carriers.unwrap_or(false), &(mode == MarketMode::Buy),
+            &credits_per_ly, &limit])?;
+
+        let mut results = vec![];
+        for row in rows {
+            results.push(NearestMarket { market: market_from_row(&row, row.try_get(13)?)?, score: row.try_get(14)? });
+        }
+        Ok::<_, ApiError>(results)
+    }).await?;
+    Ok(Json(results))
+}
+
+/// Columns of a [`CommodityMarket`] over the commodity `c`, its station `s` and the system `sy`, see [`market_from_row`].
+const MARKET_COLUMNS: &str = "c.market_id,s.name,s.system_name,sy.address,
+    coalesce(c.buy_price, 0),coalesce(c.sell_price, 0),coalesce(c.mean_price, 0),coalesce(c.stock, 0),coalesce(c.demand, 0),
+    sy.x,sy.y,sy.z,s.carrier";
+
+fn market_from_row(row: &postgres::Row, distance: Option<f64>) -> Result<CommodityMarket, ApiError> {
+    Ok(CommodityMarket {
+        market_id: row.try_get(0)?,
+        station: row.try_get(1)?,
+        system: row.try_get(2)?,
+        system_address: row.try_get(3)?,
+        buy_price: row.try_get(4)?,
+        sell_price: row.try_get(5)?,
+        mean_price: row.try_get(6)?,
+        stock: row.try_get(7)?,
+        demand: row.try_get(8)?,
+        carrier: row.try_get(12)?,
+        distance,
+    })
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![markets, nearest]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trips() {
+        for sort in [MarketSort::BuyPrice, MarketSort::SellPrice, MarketSort::Stock, MarketSort::Demand] {
+            let cursor = Cursor { sort, value: 9050, market_id: 3228342528 };
+            assert_eq!(Cursor::parse(&cursor.to_string(), sort).unwrap(), cursor);
+        }
+    }
+
+    #[test]
+    fn cursor_must_match_the_sort() {
+        let cursor = Cursor { sort: MarketSort::Stock, value: 100, market_id: 5 }.to_string();
+        assert!(Cursor::parse(&cursor, MarketSort::BuyPrice).is_err());
+        assert!(Cursor::parse("100_5", MarketSort::Stock).is_err());
+        assert!(Cursor::parse("stock_x_5", MarketSort::Stock).is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::f64::consts::TAU;
+use std::sync::Arc;
+
+use rocket::form;
+use rocket::{Route, State};
+use rocket::serde::{Deserialize, Serialize, json::Json};
+
+use super::body::BodyKind;
+use super::dlc::Dlc;
+use super::error::{query_param, ApiError};
+use super::history::now;
+use super::parent::Parent;
+use super::{cached_system, Cache, LazyDbConn};
+
+/// Newton iterations before giving up on the eccentric anomaly
+const KEPLER_ITERATIONS: usize = 50;
+const KEPLER_TOLERANCE: f64 = 1e-12;
+
+/// Position or offset in meters.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Vector {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Vector {
+    fn add(self, other: Vector) -> Vector {
+        Vector { x: self.x + other.x, y: self.y + other.y, z: self.z + other.z }
+    }
+
+    fn sub(self, other: Vector) -> Vector {
+        Vector { x: self.x - other.x, y: self.y - other.y, z: self.z - other.z }
+    }
+}
+
+/// Keplerian elements of an elliptic orbit, in the units of the journal scan.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrbitalElements {
+    /// Meters
+    pub semi_major_axis: f64,
+    pub eccentricity: f64,
+    /// Degrees
+    pub inclination: f64,
+    /// Argument of periapsis in degrees
+    pub periapsis: f64,
+    /// Longitude of the ascending node in degrees
+    pub ascending_node: f64,
+    /// Mean anomaly at the epoch in degrees
+    pub mean_anomaly: f64,
+    /// Seconds
+    pub period: f64,
+}
+
+impl OrbitalElements {
+    /// Elements of a body, if all of them are known and describe an elliptic orbit.
+    pub fn new(semi_major_axis: Option<f32>, eccentricity: Option<f32>, inclination: Option<f32>, periapsis: Option<f32>,
+               ascending_node: Option<f32>, mean_anomaly: Option<f32>, period: Option<f32>) -> Option<Self> {
+        let elements = OrbitalElements {
+            semi_major_axis: semi_major_axis? as f64,
+            eccentricity: eccentricity? as f64,
+            inclination: inclination? as f64,
+            periapsis: periapsis? as f64,
+            ascending_node: ascending_node? as f64,
+            mean_anomaly: mean_anomaly? as f64,
+            period: period? as f64,
+        };
+        let elliptic = (0.0..1.0).contains(&elements.eccentricity) && elements.semi_major_axis > 0.0 && elements.period != 0.0;
+        elliptic.then_some(elements)
+    }
+
+    /// Position relative to the orbited body `seconds` after the epoch.
+    ///
+    /// The reference plane is the one the journal measures inclination against, x points to the ascending node
+    /// of a body with an ascending node of 0.
+    pub fn position(&self, seconds: f64) -> Vector {
+        let mean_anomaly = (self.mean_anomaly.to_radians() + TAU * seconds / self.period).rem_euclid(TAU);
+        let eccentric_anomaly = eccentric_anomaly(mean_anomaly, self.eccentricity);
+
+        //Position in the orbital plane, periapsis along x
+        let a = self.semi_major_axis;
+        let e = self.eccentricity;
+        let px = a * (eccentric_anomaly.cos() - e);
+        let py = a * (1.0 - e * e).sqrt() * eccentric_anomaly.sin();
+
+        let (sin_w, cos_w) = self.periapsis.to_radians().sin_cos();
+        let (sin_i, cos_i) = self.inclination.to_radians().sin_cos();
+        let (sin_o, cos_o) = self.ascending_node.to_radians().sin_cos();
+        Vector {
+            x: (cos_o * cos_w - sin_o * sin_w * cos_i) * px + (-cos_o * sin_w - sin_o * cos_w * cos_i) * py,
+            y: (sin_o * cos_w + cos_o * sin_w * cos_i) * px + (-sin_o * sin_w + cos_o * cos_w * cos_i) * py,
+            z: sin_w * sin_i * px + cos_w * sin_i * py,
+        }
+    }
+}
+
+/// Solves Kepler's equation `M = E - e sin E` for the eccentric anomaly `E`, all angles in radians.
+pub fn eccentric_anomaly(mean_anomaly: f64, eccentricity: f64) -> f64 {
+    let mut anomaly = if eccentricity < 0.8 { mean_anomaly } else { std::f64::consts::PI };
+    for _ in 0..KEPLER_ITERATIONS {
+        let step = (anomaly - eccentricity * anomaly.sin() - mean_anomaly) / (1.0 - eccentricity * anomaly.cos());
+        anomaly -= step;
+        if step.abs() < KEPLER_TOLERANCE {
+            break;
+        }
+    }
+    anomaly
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BodyPosition {
+    pub body_id: i32,
+    pub body_name: Option<String>,
+    pub kind: BodyKind,
+    /// What the body orbits, missing for the root of the system
+    pub parent: Option<Parent>,
+    /// Missing if the orbit of the body is not fully known
+    pub relative_to_parent: Option<Vector>,
+    /// Missing if the orbit of the body or of anything between it and the arrival star is not fully known
+    pub relative_to_arrival: Option<Vector>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SystemPositions {
+    pub name: Option<String>,
+    pub address: i64,
+    pub at: i64,
+    pub epoch: i64,
+    pub bodies: Vec<BodyPosition>,
+}
+
+/// Position of every star and planet at the unix timestamp `at`, which defaults to now. Distances are in meters.
+///
+/// Scans carry the mean anomaly at the time of the scan, which is not stored. It is taken to be valid at the
+/// unix timestamp `epoch` instead, 0 unless given, so positions are consistent with each other but not with the game.
+/// Barycentres have no orbit of their own in the scan data, so bodies orbiting a barycentre only get a position
+/// relative to the arrival star if that barycentre is the root of the system.
+#[get("/<dlc>/system/<address>/positions?<at>&<epoch>")]
+async fn positions(cache: &State<Arc<Cache>>, db: LazyDbConn<'_>, address: i64, dlc: Result<Dlc, ApiError>,
+                   at: form::Result<'_, i64>, epoch: form::Result<'_, i64>) -> Result<Json<SystemPositions>, ApiError> {
+    let odyssey = dlc?.odyssey();
+    let at = query_param("at", at)?.unwrap_or_else(now);
+    let epoch = query_param("epoch", epoch)?.unwrap_or(0);
+    let system = cached_system(cache, &db, odyssey, address).await?;
+
+    //Both may be anywhere in the range of i64, so their difference is only taken as a float
+    let seconds = at as f64 - epoch as f64;
+    let mut bodies = vec![];
+    let mut arrival = None;
+    for star in system.stars.iter().flatten() {
+        let Some(body_id) = star.body_id else { continue };
+        if star.distance_from_arrival_ls == Some(0.0) {
+            arrival.get_or_insert(body_id);
+        }
+        let elements = OrbitalElements::new(star.semi_major_axis, star.eccentricity, star.orbital_inclination, star.periapsis,
+                                            star.ascending_node, star.mean_anomaly, star.orbital_period);
+        bodies.push(BodyPosition {
+            body_id,
+            body_name: star.body_name.clone(),
+            kind: BodyKind::Star,
+            parent: star.parents.first().copied(),
+            relative_to_parent: elements.map(|elements| elements.position(seconds)),
+            relative_to_arrival: None,
+        });
+    }
+    for planet in system.planets.iter().flatten() {
+        let Some(body_id) = planet.body_id else { continue };
+        let elements = OrbitalElements::new(planet.semi_major_axis, planet.eccentricity, planet.orbital_inclination, planet.periapsis,
+                                            planet.ascending_node, planet.mean_anomaly, planet.orbital_period);
+        bodies.push(BodyPosition {
+            body_id,
+            body_name: planet.body_name.clone(),
+            kind: BodyKind::Planet,
+            parent: planet.parents.first().copied(),
+            relative_to_parent: elements.map(|elements| elements.position(seconds)),
+            relative_to_arrival: None,
+        });
+    }
+
+    //Positions relative to the root of the system, which is the only point known without an orbit
+    let chains = system.stars.iter().flatten().filter_map(|star| Some((star.body_id?, star.parents.as_slice())))
+        .chain(system.planets.iter().flatten().filter_map(|planet| Some((planet.body_id?, planet.parents.as_slice()))));
+    let offsets: HashMap<i32, Option<Vector>> = bodies.iter().map(|body| (body.body_id, body.relative_to_parent)).collect();
+    let orbits: Orbits = chains.filter_map(|(body_id, parents)| Some((body_id, (*offsets.get(&body_id)?, parents)))).collect();
+
+    let absolute: HashMap<i32, Vector> = orbits.keys()
+        .filter_map(|body_id| Some((*body_id, absolute_position(*body_id, &orbits)?)))
+        .collect();
+    let arrival = arrival.and_then(|body_id| absolute.get(&body_id).copied());
+    for body in &mut bodies {
+        body.relative_to_arrival = arrival.zip(absolute.get(&body.body_id).copied())
+            .map(|(arrival, position)| position.sub(arrival));
+    }
+    bodies.sort_by_key(|body| body.body_id);
+
+    Ok(Json(SystemPositions { name: system.name, address, at, epoch, bodies }))
+}
+
+/// Offset of every body to what it orbits and its parents, nearest first, keyed by body id.
+type Orbits<'a> = HashMap<i32, (Option<Vector>, &'a [Parent])>;
+
+/// Sums up the offsets along the parents of the body until the root of the system.
+/// Unknown if any orbit on the way is unknown or the parents run in a circle.
+fn absolute_position(body_id: i32, orbits: &Orbits) -> Option<Vector> {
+    let mut position = Vector::default();
+    let mut current = body_id;
+    for _ in 0..=orbits.len() {
+        let (offset, parents) = orbits.get(&current)?;
+        let Some(parent) = parents.first() else {
+            return Some(position);
+        };
+        position = position.add((*offset)?);
+        //Barycentres orbit nothing known, so only one at the root of the system has a position
+        if let [Parent::Barycentre(_)] = parents {
+            return Some(position);
+        }
+        current = parent.body_id();
+    }
+    None
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![positions]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: f64 = 1e-9;
+
+    fn circular(inclination: f32) -> OrbitalElements {
+        OrbitalElements::new(Some(1.0), Some(0.0), Some(inclination), Some(0.0), Some(0.0), Some(0.0), Some(4.0)).unwrap()
+    }
+
+    fn assert_close(actual: Vector, expected: Vector) {
+        let offset = actual.sub(expected);
+        assert!(offset.x.abs() < TOLERANCE && offset.y.abs() < TOLERANCE && offset.z.abs() < TOLERANCE,
+                "{:?} is not {:?}", actual, expected);
+    }
+
+    #[test]
+    fn circular_orbits_turn_a_quarter_in_a_quarter_period() {
+        let elements = circular(0.0);
+        assert_close(elements.position(0.0), Vector { x: 1.0, y: 0.0, z: 0.0 });
+        assert_close(elements.position(1.0), Vector { x: 0.0, y: 1.0, z: 0.0 });
+        assert_close(elements.position(2.0), Vector { x: -1.0, y: 0.0, z: 0.0 });
+    }
+
+    #[test]
+    fn kepler_is_solved_for_high_eccentricities() {
+        let eccentricity = 0.9;
+        for step in 0..=16 {
+            let mean_anomaly = TAU * step as f64 / 16.0;
+            let anomaly = eccentric_anomaly(mean_anomaly, eccentricity);
+            let residual = anomaly - eccentricity * anomaly.sin() - mean_anomaly;
+            assert!(residual.abs() < TOLERANCE, "residual {} for mean anomaly {}", residual, mean_anomaly);
+        }
+    }
+
+    #[test]
+    fn a_right_angle_inclination_moves_the_orbit_onto_z() {
+        let elements = circular(90.0);
+        assert_close(elements.position(0.0), Vector { x: 1.0, y: 0.0, z: 0.0 });
+        assert_close(elements.position(1.0), Vector { x: 0.0, y: 0.0, z: 1.0 });
+    }
+
+    #[test]
+    fn hyperbolic_and_incomplete_orbits_have_no_elements() {
+        assert_eq!(OrbitalElements::new(Some(1.0), Some(1.0), Some(0.0), Some(0.0), Some(0.0), Some(0.0), Some(4.0)), None);
+        assert_eq!(OrbitalElements::new(Some(1.0), Some(0.0), None, Some(0.0), Some(0.0), Some(0.0), Some(4.0)), None);
+    }
+
+    #[test]
+    fn binaries_are_placed_around_a_root_barycentre() {
+        let (star, planet) = ([Parent::Barycentre(0)], [Parent::Star(1), Parent::Barycentre(0)]);
+        let orbits: Orbits = HashMap::from([
+            (1, (Some(Vector { x: 1.0, y: 0.0, z: 0.0 }), star.as_slice())),
+            (2, (Some(Vector { x: -1.0, y: 0.0, z: 0.0 }), star.as_slice())),
+            (3, (Some(Vector { x: 0.0, y: 2.0, z: 0.0 }), planet.as_slice())),
+        ]);
+        assert_eq!(absolute_position(1, &orbits), Some(Vector { x: 1.0, y: 0.0, z: 0.0 }));
+        assert_eq!(absolute_position(2, &orbits), Some(Vector { x: -1.0, y: 0.0, z: 0.0 }));
+        assert_eq!(absolute_position(3, &orbits), Some(Vector { x: 1.0, y: 2.0, z: 0.0 }));
+    }
+
+    #[test]
+    fn parent_cycles_have_no_position() {
+        let (first, second) = ([Parent::Star(2)], [Parent::Star(1)]);
+        let offset = Some(Vector { x: 1.0, y: 0.0, z: 0.0 });
+        let orbits: Orbits = HashMap::from([(1, (offset, first.as_slice())), (2, (offset, second.as_slice()))]);
+        assert_eq!(absolute_position(1, &orbits), None);
+    }
+}
@@ -0,0 +1,124 @@
+use rocket::serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::error::ApiError;
+
+/// Body a star or planet orbits, one entry of the journal's `Parents` array.
+///
+/// Serialized as `{"type": "barycentre", "body_id": 3}`. The journal calls barycentres `Null`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", tag = "type", content = "body_id", rename_all = "lowercase")]
+pub enum Parent {
+    Star(i32),
+    Planet(i32),
+    Barycentre(i32),
+    Ring(i32),
+}
+
+impl Parent {
+    /// Parses a `parent_type` as written by the journal, `None` for types unknown to the api.
+    pub fn from_journal(kind: &str, body_id: i32) -> Option<Self> {
+        match kind {
+            "Star" => Some(Parent::Star(body_id)),
+            "Planet" => Some(Parent::Planet(body_id)),
+            "Null" => Some(Parent::Barycentre(body_id)),
+            "Ring" => Some(Parent::Ring(body_id)),
+            _ => None,
+        }
+    }
+
+    pub fn body_id(self) -> i32 {
+        match self {
+            Parent::Star(body_id) | Parent::Planet(body_id) | Parent::Barycentre(body_id) | Parent::Ring(body_id) => body_id,
+        }
+    }
+
+    /// Journal style object like `{"Null": 3}`.
+    pub fn journal(self) -> Value {
+        let kind = match self {
+            Parent::Star(_) => "Star",
+            Parent::Planet(_) => "Planet",
+            Parent::Barycentre(_) => "Null",
+            Parent::Ring(_) => "Ring",
+        };
+        json!({ kind: self.body_id() })
+    }
+}
+
+/// Shape of the `parents` of every body in a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, FromFormField)]
+pub enum ParentFormat {
+    /// Tagged objects as serialized by [`Parent`]
+    #[default]
+    #[field(value = "typed")]
+    Typed,
+    /// Objects like `{"Null": 3}` as in the journal, the format of earlier api versions
+    #[field(value = "journal")]
+    Journal,
+}
+
+impl ParentFormat {
+    /// Serializes `value` and rewrites every `parents` array in it to this format, however deeply nested.
+    pub fn apply<T: Serialize>(self, value: &T) -> Result<Value, ApiError> {
+        let mut value = serde_json::to_value(value).map_err(|e| ApiError::Internal(e.to_string()))?;
+        if self == ParentFormat::Journal {
+            to_journal(&mut value)?;
+        }
+        Ok(value)
+    }
+}
+
+fn to_journal(value: &mut Value) -> Result<(), ApiError> {
+    match value {
+        Value::Object(object) => {
+            for (key, field) in object.iter_mut() {
+                if key == "parents" && field.is_array() {
+                    let typed: Vec<Parent> = serde_json::from_value(field.take()).map_err(|e| ApiError::Internal(e.to_string()))?;
+                    *field = typed.into_iter().map(Parent::journal).collect();
+                } else {
+                    to_journal(field)?;
+                }
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                to_journal(value)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn unknown_journal_types_are_none() {
+        assert_eq!(Parent::from_journal("Null", 3), Some(Parent::Barycentre(3)));
+        assert_eq!(Parent::from_journal("Ring", 7), Some(Parent::Ring(7)));
+        assert_eq!(Parent::from_journal("Wormhole", 1), None);
+    }
+
+    #[test]
+    fn typed_format_is_serialized_unchanged() {
+        let value = json!({"parents": [{"type": "star", "body_id": 0}]});
+        assert_eq!(ParentFormat::Typed.apply(&value).unwrap(), value);
+    }
+
+    #[test]
+    fn journal_format_rewrites_nested_parents() {
+        let value = json!({
+            "results": [{"body_id": 4, "parents": [{"type": "planet", "body_id": 2}, {"type": "barycentre", "body_id": 1}]}],
+            "bodies": [{"kind": "star", "parents": [], "children": [{"kind": "ring", "parents": [{"type": "ring", "body_id": 5}]}]}],
+        });
+        let expected = json!({
+            "results": [{"body_id": 4, "parents": [{"Planet": 2}, {"Null": 1}]}],
+            "bodies": [{"kind": "star", "parents": [], "children": [{"kind": "ring", "parents": [{"Ring": 5}]}]}],
+        });
+        assert_eq!(ParentFormat::Journal.apply(&value).unwrap(), expected);
+    }
+}
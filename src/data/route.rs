@@ -0,0 +1,296 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use rocket::{Route, State};
+use rocket::serde::{Deserialize, Serialize, json::Json};
+use rocket_sync_db_pools::postgres;
+
+use super::dlc::Dlc;
+use super::error::ApiError;
+use super::systems::{load_coordinates, Coordinates, SystemSummary, POSITION};
+use super::LazyDbConn;
+
+/// Longest jump range accepted, well above what any ship can reach
+const MAX_JUMP_RANGE: f64 = 200.0;
+
+/// The `[route]` section of `Rocket.toml`, bounding the work of a single route request.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct RouteConfig {
+    /// Light years a system may be away from the straight line between start and destination to be used
+    pub corridor_width: f64,
+    /// Most systems loaded from the corridor, longer routes through denser regions are refused
+    pub max_systems: i64,
+    /// Most distances between a system and its possible neighbours computed by the search before giving up,
+    /// which bounds its work however densely the systems are packed
+    pub max_distance_checks: usize,
+}
+
+impl Default for RouteConfig {
+    fn default() -> Self {
+        RouteConfig {
+            corridor_width: 50.0,
+            max_systems: 200_000,
+            max_distance_checks: 20_000_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Jump {
+    pub system: SystemSummary,
+    /// Light years of this jump
+    pub distance: f64,
+    /// Light years from the start up to this system
+    pub total_distance: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PlottedRoute {
+    pub from: SystemSummary,
+    pub to: SystemSummary,
+    pub jump_range: f64,
+    pub total_distance: f64,
+    /// Every system jumped to in order, ending with `to`
+    pub jumps: Vec<Jump>,
+}
+
+/// Route with the fewest jumps of at most `jump_range` light years from one system to another,
+/// the shorter one in light years among equally many jumps.
+///
+/// Only systems within the corridor around the straight line are considered, see [`RouteConfig`].
+/// Routes which need more systems or search steps than configured are refused.
+#[get("/<dlc>/route?<from>&<to>&<jump_range>")]
+async fn route(db: LazyDbConn<'_>, config: &State<RouteConfig>, dlc: Result<Dlc, ApiError>, from: i64, to: i64, jump_range: f64) -> Result<Json<PlottedRoute>, ApiError> {
+    let odyssey = dlc?.odyssey();
+    if !(jump_range > 0.0 && jump_range <= MAX_JUMP_RANGE) {
+        return Err(ApiError::BadRequest(format!("Parameter jump_range must be greater than 0 and at most {}", MAX_JUMP_RANGE)));
+    }
+    let config = *config.inner();
+
+    let systems = {
+        let db = db.get().await?;
+        db.run(move |conn| load_corridor(conn, odyssey, from, to, &config)).await?
+    };
+    rocket::tokio::task::spawn_blocking(move || plot(systems, from, to, jump_range, config.max_distance_checks))
+        .await
+        .map_err(|e| ApiError::Internal(e.to_string()))?
+        .map(Json)
+}
+
+/// Systems with known coordinates within the corridor between `from` and `to`.
+///
+/// The bounding box of the corridor is looked up in the spatial index, the distance to the segment
+/// between start and destination is checked before the limit so only systems in the corridor count against it.
+fn load_corridor(conn: &mut postgres::Client, odyssey: bool, from: i64, to: i64, config: &RouteConfig) -> Result<Vec<(SystemSummary, Coordinates)>, ApiError> {
+    let start = load_coordinates(conn, from, odyssey)?;
+    let end = load_coordinates(conn, to, odyssey)?;
+    let width = config.corridor_width;
+    let (dx, dy, dz) = (end.x - start.x, end.y - start.y, end.z - start.z);
+    let length = dx * dx + dy * dy + dz * dz;
+    //Start and destination in the same place make every point of the segment the start
+    let inverse_length = if length == 0.0 { 0.0 } else { 1.0 / length };
+
+    //Nearest point of the segment at t between 0 at the start and 1 at the destination
+    //language=postgresql
+    let sql = format!("select sy.name,sy.address,sy.x,sy.y,sy.z from system sy
+            cross join lateral (select greatest(0, least(1, ((sy.x - $4::float8) * $7::float8 + (sy.y - $5::float8) * $8::float8
+                + (sy.z - $6::float8) * $9::float8) * $10::float8)) as t) segment
+        where sy.odyssey = $1
+          and {POSITION} <@ cube($2::float8[], $3::float8[])
+          and power(sy.x - ($4 + segment.t * $7), 2) + power(sy.y - ($5 + segment.t * $8), 2) + power(sy.z - ($6 + segment.t * $9), 2) <= $11::float8
+        limit $12");
+    let low = vec![start.x.min(end.x) - width, start.y.min(end.y) - width, start.z.min(end.z) - width];
+    let high = vec![start.x.max(end.x) + width, start.y.max(end.y) + width, start.z.max(end.z) + width];
+    let rows = conn.query(sql.as_str(), &[&odyssey, &low, &high, &start.x, &start.y, &start.z, &dx, &dy, &dz, &inverse_length,
+        &(width * width), &(config.max_systems + 1)])?;
+    if rows.len() as i64 > config.max_systems {
+        return Err(ApiError::BadRequest(format!("Route passes more than {} systems, plot it in shorter legs", config.max_systems)));
+    }
+
+    let mut systems = vec![];
+    for row in rows {
+        let system = SystemSummary {
+            name: row.try_get(0)?,
+            address: row.try_get(1)?,
+            x: row.try_get(2)?,
+            y: row.try_get(3)?,
+            z: row.try_get(4)?,
+        };
+        if let Some(position) = Coordinates::from_columns(system.x, system.y, system.z) {
+            systems.push((system, position));
+        }
+    }
+    Ok(systems)
+}
+
+/// Entry of the open set, ordered so the [`BinaryHeap`] pops the fewest estimated jumps first,
+/// then the shortest estimated distance.
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    jumps: usize,
+    distance: f64,
+    system: usize,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.jumps.cmp(&self.jumps)
+            .then(other.distance.total_cmp(&self.distance))
+            .then(other.system.cmp(&self.system))
+    }
+}
+
+/// A* over the loaded systems. Costs are counted in jumps, the estimate to the destination being
+/// its straight line distance divided by the jump range, so the route found has the fewest jumps possible.
+fn plot(systems: Vec<(SystemSummary, Coordinates)>, from: i64, to: i64, jump_range: f64, max_distance_checks: usize) -> Result<PlottedRoute, ApiError> {
+    let index_of = |address: i64| systems.iter().position(|(system, _)| system.address == address);
+    let start = index_of(from).ok_or_else(|| ApiError::NotFound(format!("System {} has no known coordinates", from)))?;
+    let goal = index_of(to).ok_or_else(|| ApiError::NotFound(format!("System {} has no known coordinates", to)))?;
+    let goal_position = systems[goal].1;
+    let estimate = |system: usize| {
+        let distance = systems[system].1.distance(&goal_position);
+        ((distance / jump_range).ceil() as usize, distance)
+    };
+
+    //Grid of cells one jump range wide, so neighbours are always in the surrounding 27 cells
+    let cell = |position: &Coordinates| {
+        ((position.x / jump_range).floor() as i64, (position.y / jump_range).floor() as i64, (position.z / jump_range).floor() as i64)
+    };
+    let mut grid: HashMap<(i64, i64, i64), Vec<usize>> = HashMap::new();
+    for (index, (_, position)) in systems.iter().enumerate() {
+        grid.entry(cell(position)).or_default().push(index);
+    }
+
+    //Best known jumps and distance to each system and where it was reached from
+    let mut best: HashMap<usize, (usize, f64, Option<usize>)> = HashMap::from([(start, (0, 0.0, None))]);
+    let mut open = BinaryHeap::new();
+    let (jumps, distance) = estimate(start);
+    open.push(Candidate { jumps, distance, system: start });
+    let mut expanded = HashSet::new();
+    let mut distance_checks = 0;
+
+    while let Some(Candidate { system, .. }) = open.pop() {
+        let (jumps, travelled, _) = best[&system];
+        if system == goal {
+            return Ok(build_route(&systems, &best, start, goal, jump_range));
+        }
+        //Systems get pushed again whenever a better way to them is found, the older entries are stale
+        if !expanded.insert(system) {
+            continue;
+        }
+
+        let position = systems[system].1;
+        let (cx, cy, cz) = cell(&position);
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    for &next in grid.get(&(cx + dx, cy + dy, cz + dz)).into_iter().flatten() {
+                        distance_checks += 1;
+                        if distance_checks > max_distance_checks {
+                            return Err(ApiError::BadRequest(format!("No route found within {} search steps, plot it in shorter legs", max_distance_checks)));
+                        }
+                        let jump = position.distance(&systems[next].1);
+                        if next == system || jump > jump_range {
+                            continue;
+                        }
+                        let (next_jumps, next_travelled) = (jumps + 1, travelled + jump);
+                        let improves = best.get(&next).is_none_or(|&(known_jumps, known_travelled, _)| {
+                            (next_jumps, next_travelled) < (known_jumps, known_travelled)
+                        });
+                        if improves {
+                            best.insert(next, (next_jumps, next_travelled, Some(system)));
+                            let (remaining_jumps, remaining_distance) = estimate(next);
+                            open.push(Candidate { jumps: next_jumps + remaining_jumps, distance: next_travelled + remaining_distance, system: next });
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Err(ApiError::NotFound(format!("No route from {} to {} with a jump range of {} ly", from, to, jump_range)))
+}
+
+fn build_route(systems: &[(SystemSummary, Coordinates)], best: &HashMap<usize, (usize, f64, Option<usize>)>, start: usize, goal: usize, jump_range: f64) -> PlottedRoute {
+    let mut path = vec![goal];
+    while let Some(&(_, _, Some(previous))) = best.get(path.last().unwrap_or(&start)) {
+        path.push(previous);
+    }
+    path.reverse();
+
+    let jumps: Vec<Jump> = path.windows(2).map(|pair| Jump {
+        system: systems[pair[1]].0.clone(),
+        distance: systems[pair[0]].1.distance(&systems[pair[1]].1),
+        total_distance: best[&pair[1]].1,
+    }).collect();
+    PlottedRoute {
+        from: systems[start].0.clone(),
+        to: systems[goal].0.clone(),
+        jump_range,
+        total_distance: best[&goal].1,
+        jumps,
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![route]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system(address: i64, x: f64, y: f64) -> (SystemSummary, Coordinates) {
+        let summary = SystemSummary { name: Some(format!("System {}", address)), address, x: Some(x as f32), y: Some(y as f32), z: Some(0.0) };
+        (summary, Coordinates { x, y, z: 0.0 })
+    }
+
+    fn addresses(route: &PlottedRoute) -> Vec<i64> {
+        route.jumps.iter().map(|jump| jump.system.address).collect()
+    }
+
+    #[test]
+    fn fewest_jumps_win_over_a_shorter_distance() {
+        let systems = vec![system(1, 0.0, 0.0), system(2, 4.0, 0.0), system(3, 8.0, 0.0), system(4, 6.0, 3.0), system(5, 12.0, 0.0)];
+        let route = plot(systems, 1, 5, 7.0, 1000).unwrap();
+        assert_eq!(addresses(&route), vec![4, 5]);
+        assert!((route.total_distance - 2.0 * 45f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn equally_many_jumps_take_the_shorter_distance() {
+        let systems = vec![system(1, 0.0, 0.0), system(2, 6.0, 3.0), system(3, 6.0, 1.0), system(4, 12.0, 0.0)];
+        let route = plot(systems, 1, 4, 7.0, 1000).unwrap();
+        assert_eq!(addresses(&route), vec![3, 4]);
+        assert_eq!(route.jumps[1].total_distance, route.total_distance);
+    }
+
+    #[test]
+    fn unreachable_destinations_are_not_found() {
+        let systems = vec![system(1, 0.0, 0.0), system(2, 4.0, 0.0), system(3, 20.0, 0.0)];
+        assert!(matches!(plot(systems, 1, 3, 5.0, 1000), Err(ApiError::NotFound(_))));
+    }
+
+    #[test]
+    fn searches_over_the_budget_are_refused() {
+        let line: Vec<_> = (0..100).map(|address| system(address, address as f64, 0.0)).collect();
+        assert!(matches!(plot(line.clone(), 0, 99, 1.5, 100), Err(ApiError::BadRequest(_))));
+        assert_eq!(plot(line, 0, 99, 1.5, 10_000).unwrap().jumps.len(), 99);
+    }
+}
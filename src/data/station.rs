@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use rocket::{Route, State};
+use rocket::serde::{Deserialize, Serialize, json::Json};
+use rocket_sync_db_pools::postgres;
+
+use super::dlc::Dlc;
+use super::error::ApiError;
+use super::{Cache, LazyDbConn};
+
+/// Most recent locations returned with a carrier
+const MAX_CARRIER_LOCATIONS: i64 = 100;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Station {
+    pub market_id: i64,
+    pub name: Option<String>,
+    pub system_name: Option<String>,
+    pub carrier: bool,
+    pub market: Vec<MarketEntry>,
+}
+
+/// One commodity traded at a station.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct MarketEntry {
+    pub name: String,
+    pub buy_price: Option<i32>,
+    pub sell_price: Option<i32>,
+    pub mean_price: Option<i32>,
+    pub stock: Option<i32>,
+    pub demand: Option<i32>,
+}
+
+#[get("/<dlc>/station/<market_id>")]
+async fn station(cache: &State<Arc<Cache>>, db: LazyDbConn<'_>, market_id: i64, dlc: Result<Dlc, ApiError>) -> Result<Json<Station>, ApiError> {
+    let odyssey = dlc?.odyssey();
+    let station = cache.station.get_or_load((odyssey, market_id), || async move {
+        let db = db.get().await?;
+        db.run(move |conn| load_station(conn, market_id, odyssey)).await
+    }).await;
+    station.map(Json)
+}
+
+/// A system a fleet carrier was seen in.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CarrierLocation {
+    pub system_name: String,
+    /// Unix timestamp the carrier was first seen in the system
+    pub arrived: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Carrier {
+    #[serde(flatten)]
+    pub station: Station,
+    /// Most recent first, at most [`MAX_CARRIER_LOCATIONS`]
+    pub locations: Vec<CarrierLocation>,
+}
+
+/// A fleet carrier by callsign with its current system, market and the systems it was seen in before.
+///
+/// Locations are recorded by the `carrier_location` trigger whenever the system of a carrier changes,
+/// see `sql/carriers.sql`.
+#[get("/<dlc>/carrier/<callsign>")]
+async fn carrier(cache: &State<Arc<Cache>>, db: LazyDbConn<'_>, callsign: &str, dlc: Result<Dlc, ApiError>) -> Result<Json<Carrier>, ApiError> {
+    let odyssey = dlc?.odyssey();
+    let callsign = callsign.trim().to_uppercase();
+    let (market_id, locations) = {
+        let db = db.get().await?;
+        db.run(move |conn| {
+            //The same callsign may show up on several stations, the newest market wins
+            //language=postgresql
+            let sql = "select market_id from station where name = $1 and carrier order by market_id desc limit 1";
+            let market_id: i64 = conn.query_opt(sql, &[&callsign])?
+                .ok_or_else(|| ApiError::NotFound(format!("Carrier {} not found", callsign)))?
+                .try_get(0)?;
+
+            //language=postgresql
+            let sql = "select system_name,arrived from carrier_location where market_id = $1 order by arrived desc, id desc limit $2";
+            let mut locations = vec![];
+            for row in conn.query(sql, &[&market_id, &MAX_CARRIER_LOCATIONS])? {
+                locations.push(CarrierLocation {
+                    system_name: row.try_get(0)?,
+                    arrived: row.try_get(1)?,
+                });
+            }
+            Ok::<_, ApiError>((market_id, locations))
+        }).await?
+    };
+    let station = cache.station.get_or_load((odyssey, market_id), || async move {
+        let db = db.get().await?;
+        db.run(move |conn| load_station(conn, market_id, odyssey)).await
+    }).await?;
+    Ok(Json(Carrier { station, locations }))
+}
+
+/// Every station in the system together with its market.
+#[get("/<dlc>/system/<address>/stations")]
+async fn system_stations(cache: &State<Arc<Cache>>, db: LazyDbConn<'_>, address: i64, dlc: Result<Dlc, ApiError>) -> Result<Json<Vec<Station>>, ApiError> {
+    let odyssey = dlc?.odyssey();
+    let stations = cache.system_stations.get_or_load((odyssey, address), || async move {
+        let db = db.get().await?;
+        db.run(move |conn| {
+            //language=postgresql
+            let sql = "select name from system where address = $1 and odyssey = $2";
+            let system_name: String = conn.query_opt(sql, &[&address, &odyssey])?
+                .ok_or_else(|| ApiError::NotFound(format!("System {} not found", address)))?
+                .try_get(0)?;
+
+            //language=postgresql
+            let sql = "select market_id,name,system_name,carrier from station where system_name = $1 order by name, market_id";
+            let mut stations = vec![];
+            for row in conn.query(sql, &[&system_name])? {
+                stations.push(Station {
+                    market_id: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                    system_name: row.try_get(2)?,
+                    carrier: row.try_get(3)?,
+                    market: vec![],
+                });
+            }
+            load_markets(conn, &mut stations, odyssey)?;
+            Ok(stations)
+        }).await
+    }).await;
+    stations.map(Json)
+}
+
+fn load_station(conn: &mut postgres::Client, market_id: i64, odyssey: bool) -> Result<Station, ApiError> {
+    //language=postgresql
+    let sql = "select market_id,name,system_name,carrier from station where market_id = $1";
+    let row = conn.query_opt(sql, &[&market_id])?
+        .ok_or_else(|| ApiError::NotFound(format!("Station {} not found", market_id)))?;
+    let mut stations = vec![Station {
+        market_id: row.try_get(0)?,
+        name: row.try_get(1)?,
+        system_name: row.try_get(2)?,
+        carrier: row.try_get(3)?,
+        market: vec![],
+    }];
+    load_markets(conn, &mut stations, odyssey)?;
+    Ok(stations.remove(0))
+}
+
+/// Fills in the markets of all given stations with a single query.
+fn load_markets(conn: &mut postgres::Client, stations: &mut [Station], odyssey: bool) -> Result<(), ApiError> {
+    let market_ids: Vec<i64> = stations.iter().map(|station| station.market_id).collect();
+    let mut markets: HashMap<i64, Vec<MarketEntry>> = HashMap::new();
+
+    //language=postgresql
+    let sql = "select market_id,name,buy_price,sell_price,mean_price,stock,demand from commodity
+        where market_id = any($1) and odyssey = $2 order by name";
+    for row in conn.query(sql, &[&market_ids, &odyssey])? {
+        markets.entry(row.try_get(0)?).or_default().push(MarketEntry {
+            name: row.try_get(1)?,
+            buy_price: row.try_get(2)?,
+            sell_price: row.try_get(3)?,
+            mean_price: row.try_get(4)?,
+            stock: row.try_get(5)?,
+            demand: row.try_get(6)?,
+        });
+    }
+
+    for station in stations {
+        station.market = markets.remove(&station.market_id).unwrap_or_default();
+    }
+    Ok(())
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![station, carrier, system_stations]
+}
@@ -0,0 +1,230 @@
+use rocket::Route;
+use rocket::serde::{Deserialize, Serialize, json::Json};
+use rocket_sync_db_pools::postgres;
+
+use super::dlc::Dlc;
+use super::error::ApiError;
+use super::{page_bounds, LazyDbConn, Page};
+
+/// Largest radius in light years a spatial query may cover
+pub const MAX_RADIUS: f64 = 500.0;
+
+/// Position of the system aliased `sy` as a point, the expression covered by the spatial index in `sql/indexes.sql`.
+/// Spatial queries first match it with `<@` against a box around the searched area, so postgres answers
+/// them from that index and only computes the exact distance for the systems inside the box.
+pub const POSITION: &str = "cube(array[sy.x, sy.y, sy.z]::float8[])";
+
+/// Light years from the system aliased `sy` to the point in the parameters `$x`, `$x + 1` and `$x + 2`.
+pub fn distance_sql(x: usize) -> String {
+    format!("sqrt(power(sy.x - ${}::float8, 2) + power(sy.y - ${}::float8, 2) + power(sy.z - ${}::float8, 2))", x, x + 1, x + 2)
+}
+
+/// Whether the system aliased `sy` lies within the radius in parameter `$radius` of the point in the parameters
+/// starting at `$x`, see [`distance_sql`]. The box is matched against the spatial index first, see [`POSITION`].
+pub fn within_radius_sql(x: usize, radius: usize) -> String {
+    format!("{POSITION} <@ cube_enlarge(cube(array[${}, ${}, ${}]::float8[]), ${radius}::float8, 3)
+          and {} <= ${radius}::float8", x, x + 1, x + 2, distance_sql(x))
+}
+
+/// Short form of a [`super::System`] used by every endpoint listing systems.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SystemSummary {
+    pub name: Option<String>,
+    pub address: i64,
+    pub x: Option<f32>,
+    pub y: Option<f32>,
+    pub z: Option<f32>,
+}
+
+/// Autocomplete over the system names. Matches every system whose name starts with `name`, ignoring case.
+#[get("/<dlc>/systems/search?<name>&<limit>&<offset>")]
+async fn search(db: LazyDbConn<'_>, dlc: Result<Dlc, ApiError>, name: &str, limit: Option<i64>, offset: Option<i64>) -> Result<Json<Page<SystemSummary>>, ApiError> {
+    let odyssey = dlc?.odyssey();
+    if name.trim().is_empty() {
+        return Err(ApiError::BadRequest("Parameter name must not be empty".to_string()));
+    }
+    let (limit, offset) = page_bounds(limit, offset)?;
+    let pattern = format!("{}%", escape_like(&name.trim().to_lowercase()));
+
+    let db = db.get().await?;
+    let page = db.run(move |conn| {
+        //language=postgresql
+        let sql = "select name,address,x,y,z from system where odyssey = $1 and lower(name) like $2
+            order by lower(name), address limit $3 offset $4";
+        //One more than requested to know if there is a next page
+        let rows = conn.query(sql, &[&odyssey, &pattern, &(limit + 1), &offset])?;
+
+        let mut systems = vec![];
+        for row in rows {
+            systems.push(SystemSummary {
+                name: row.try_get(0)?,
+                address: row.try_get(1)?,
+                x: row.try_get(2)?,
+                y: row.try_get(3)?,
+                z: row.try_get(4)?,
+            });
+        }
+        Ok::<_, ApiError>(Page::new(systems, limit, offset))
+    }).await?;
+    Ok(Json(page))
+}
+
+/// Position of a system in light years.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Coordinates {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Coordinates {
+    /// Coordinates from the nullable `x`, `y` and `z` columns of the system table.
+    pub fn from_columns(x: Option<f32>, y: Option<f32>, z: Option<f32>) -> Option<Self> {
+        Some(Coordinates { x: x? as f64, y: y? as f64, z: z? as f64 })
+    }
+
+    pub fn distance(&self, other: &Coordinates) -> f64 {
+        ((self.x - other.x).powi(2) + (self.y - other.y).powi(2) + (self.z - other.z).powi(2)).sqrt()
+    }
+}
+
+/// Coordinates of the system with the given address.
+pub fn load_coordinates(conn: &mut postgres::Client, address: i64, odyssey: bool) -> Result<Coordinates, ApiError> {
+    //language=postgresql
+    let sql = "select x,y,z from system where address = $1 and odyssey = $2";
+    let row = conn.query_opt(sql, &[&address, &odyssey])?
+        .ok_or_else(|| ApiError::NotFound(format!("System {} not found", address)))?;
+    Coordinates::from_columns(row.try_get(0)?, row.try_get(1)?, row.try_get(2)?)
+        .ok_or_else(|| ApiError::NotFound(format!("System {} has no known coordinates", address)))
+}
+
+/// Checks a radius parameter against [`MAX_RADIUS`].
+pub fn check_radius(radius: f64) -> Result<f64, ApiError> {
+    if !(radius > 0.0 && radius <= MAX_RADIUS) {
+        return Err(ApiError::BadRequest(format!("Parameter radius must be greater than 0 and at most {}", MAX_RADIUS)));
+    }
+    Ok(radius)
+}
+
+/// Optional filters of the spatial system queries, compared case insensitive.
+#[derive(Debug, Clone, Default, FromForm)]
+pub struct SystemFilter {
+    pub allegiance: Option<String>,
+    pub economy: Option<String>,
+    pub government: Option<String>,
+    pub security: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NearbySystem {
+    #[serde(flatten)]
+    pub system: SystemSummary,
+    pub allegiance: Option<String>,
+    pub economy: Option<String>,
+    pub government: Option<String>,
+    pub security: Option<String>,
+    /// Distance to the queried position in light years
+    pub distance: f64,
+}
+
+/// Systems within `radius` light years around the given position, nearest first.
+#[get("/<dlc>/systems/nearby?<x>&<y>&<z>&<radius>&<limit>&<offset>&<filter..>")]
+#[allow(clippy::too_many_arguments)]
+async fn nearby(db: LazyDbConn<'_>, dlc: Result<Dlc, ApiError>, x: f64, y: f64, z: f64, radius: f64, limit: Option<i64>, offset: Option<i64>, filter: SystemFilter) -> Result<Json<Page<NearbySystem>>, ApiError> {
+    let odyssey = dlc?.odyssey();
+    let radius = check_radius(radius)?;
+    let (limit, offset) = page_bounds(limit, offset)?;
+    let center = Coordinates { x, y, z };
+
+    let db = db.get().await?;
+    let page = db.run(move |conn| {
+        let systems = query_nearby(conn, odyssey, center, radius, &filter, None, limit + 1, offset)?;
+        Ok::<_, ApiError>(Page::new(systems, limit, offset))
+    }).await?;
+    Ok(Json(page))
+}
+
+/// Systems within `radius` light years around the given system, nearest first. The system itself is not part of the result.
+#[get("/<dlc>/system/<address>/neighbours?<radius>&<limit>&<offset>&<filter..>")]
+async fn neighbours(db: LazyDbConn<'_>, dlc: Result<Dlc, ApiError>, address: i64, radius: f64, limit: Option<i64>, offset: Option<i64>, filter: SystemFilter) -> Result<Json<Page<NearbySystem>>, ApiError> {
+    let odyssey = dlc?.odyssey();
+    let radius = check_radius(radius)?;
+    let (limit, offset) = page_bounds(limit, offset)?;
+
+    let db = db.get().await?;
+    let page = db.run(move |conn| {
+        let center = load_coordinates(conn, address, odyssey)?;
+        let systems = query_nearby(conn, odyssey, center, radius, &filter, Some(address), limit + 1, offset)?;
+        Ok::<_, ApiError>(Page::new(systems, limit, offset))
+    }).await?;
+    Ok(Json(page))
+}
+
+/// Runs the spatial query behind [`nearby`] and [`neighbours`].
+///
+/// The box around the sphere lets postgres answer the query from the spatial index (see [`POSITION`])
+/// instead of computing the distance to every system in the galaxy.
+#[allow(clippy::too_many_arguments)]
+fn query_nearby(conn: &mut postgres::Client, odyssey: bool, center: Coordinates, radius: f64, filter: &SystemFilter,
+                exclude: Option<i64>, limit: i64, offset: i64) -> Result<Vec<NearbySystem>, ApiError> {
+    let (distance, within_radius) = (distance_sql(2), within_radius_sql(2, 5));
+    //language=postgresql
+    let sql = format!("select sy.name,sy.address,sy.x,sy.y,sy.z,sy.allegiance,sy.economy,sy.government,sy.security,
+            {distance} as distance
+        from system sy
+        where sy.odyssey = $1
+          and {within_radius}
+          and ($6::text is null or lower(sy.allegiance) = lower($6))
+          and ($7::text is null or lower(sy.economy) = lower($7))
+          and ($8::text is null or lower(sy.government) = lower($8))
+          and ($9::text is null or lower(sy.security) = lower($9))
+          and ($10::bigint is null or sy.address <> $10)
+        order by distance, sy.address
+        limit $11 offset $12");
+    let rows = conn.query(sql.as_str(), &[&odyssey, &center.x, &center.y, &center.z, &radius,
+        &filter.allegiance, &filter.economy, &filter.government, &filter.security, &exclude, &limit, &offset])?;
+
+    let mut systems = vec![];
+    for row in rows {
+        systems.push(NearbySystem {
+            system: SystemSummary {
+                name: row.try_get(0)?,
+                address: row.try_get(1)?,
+                x: row.try_get(2)?,
+                y: row.try_get(3)?,
+                z: row.try_get(4)?,
+            },
+            allegiance: row.try_get(5)?,
+            economy: row.try_get(6)?,
+            government: row.try_get(7)?,
+            security: row.try_get(8)?,
+            distance: row.try_get(9)?,
+        });
+    }
+    Ok(systems)
+}
+
+/// Escapes the wildcards of a `like` pattern so user input is matched literally.
+fn escape_like(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![search, nearby, neighbours]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spatial_predicate_numbers_its_parameters() {
+        assert_eq!(distance_sql(3), "sqrt(power(sy.x - $3::float8, 2) + power(sy.y - $4::float8, 2) + power(sy.z - $5::float8, 2))");
+        let predicate = within_radius_sql(3, 6);
+        assert!(predicate.starts_with("cube(array[sy.x, sy.y, sy.z]::float8[]) <@ cube_enlarge(cube(array[$3, $4, $5]::float8[]), $6::float8, 3)"));
+        assert!(predicate.ends_with(&format!("{} <= $6::float8", distance_sql(3))));
+    }
+}
@@ -0,0 +1,624 @@
+use std::collections::HashMap;
+
+use rocket::{form, Route};
+use rocket::serde::{Deserialize, Serialize, json::Json};
+use rocket_sync_db_pools::postgres;
+
+use super::dlc::Dlc;
+use super::error::{query_param, ApiError};
+use super::systems::{distance_sql, load_coordinates, within_radius_sql, Coordinates};
+use super::{page_bounds, LazyDbConn};
+
+/// Largest radius in light years the trade endpoints search for stations
+pub const MAX_TRADE_RADIUS: f64 = 100.0;
+/// Most stations the trade endpoints compare, the nearest ones are kept. Every ordered pair of them gets checked,
+/// so this bounds the work of a single request in dense regions like the bubble.
+pub const MAX_TRADE_STATIONS: i64 = 250;
+/// Same thresholds the commodity summary uses to skip nearly empty markets
+pub const DEFAULT_MIN_STOCK: i32 = 1000;
+pub const DEFAULT_MIN_DEMAND: i32 = 1000;
+/// Commodities listed per station pair
+const COMMODITIES_PER_ROUTE: usize = 3;
+/// Estimated seconds per hyperspace jump, including the charge up and the cooldown
+const SECONDS_PER_JUMP: f64 = 60.0;
+/// Estimated seconds per stop: undocking, supercruise to the station, docking and trading
+const SECONDS_PER_STOP: f64 = 180.0;
+/// Only the most profitable hops leaving a station are followed by the loop search
+const LOOP_BRANCHING: usize = 8;
+const MAX_LOOP_HOPS: usize = 5;
+
+/// A station together with every commodity it buys or sells in useful amounts.
+#[derive(Debug, Clone)]
+pub struct TradeStation {
+    pub market_id: i64,
+    pub station: Option<String>,
+    pub system: Option<String>,
+    pub system_address: i64,
+    pub carrier: bool,
+    pub position: Coordinates,
+    pub offers: Vec<Offer>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Offer {
+    pub commodity: String,
+    pub buy_price: i32,
+    pub sell_price: i32,
+    pub stock: i32,
+    pub demand: i32,
+}
+
+/// Station as referenced in a trade route.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct StationRef {
+    pub market_id: i64,
+    pub station: Option<String>,
+    pub system: Option<String>,
+    pub system_address: i64,
+    pub carrier: bool,
+}
+
+impl From<&TradeStation> for StationRef {
+    fn from(station: &TradeStation) -> Self {
+        StationRef {
+            market_id: station.market_id,
+            station: station.station.clone(),
+            system: station.system.clone(),
+            system_address: station.system_address,
+            carrier: station.carrier,
+        }
+    }
+}
+
+/// Buying a commodity at one station and selling it at another.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Trade {
+    pub commodity: String,
+    pub buy_price: i32,
+    pub sell_price: i32,
+    pub profit_per_ton: i32,
+    /// Tons limited by cargo space, stock at the source and demand at the destination
+    pub quantity: i32,
+    pub total_profit: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TradeRoute {
+    pub source: StationRef,
+    pub destination: StationRef,
+    /// Light years between the two systems
+    pub distance: f64,
+    /// Light years from the `from` system to the source
+    pub distance_from_start: f64,
+    /// Most profitable commodities first
+    pub trades: Vec<Trade>,
+}
+
+/// Filters shared by the trade endpoints.
+#[derive(Debug, Clone, Copy)]
+pub struct TradeLimits {
+    pub cargo: i32,
+    pub min_profit: i32,
+    pub min_stock: i32,
+    pub min_demand: i32,
+    /// Whether fleet carriers may be used as source or destination
+    pub include_carriers: bool,
+}
+
+/// Best single hop trades between stations within `radius` light years of the `from` system.
+/// Only the nearest [`MAX_TRADE_STATIONS`] stations with a useful market are compared.
+#[get("/<dlc>/trade/routes?<from>&<radius>&<cargo>&<min_profit>&<min_stock>&<min_demand>&<limit>&<include_carriers>")]
+#[allow(clippy::too_many_arguments)]
+async fn routes_single_hop(db: LazyDbConn<'_>, dlc: Result<Dlc, ApiError>, from: i64, radius: form::Result<'_, f64>, cargo: i32,
+                           min_profit: form::Result<'_, i32>, min_stock: form::Result<'_, i32>, min_demand: form::Result<'_, i32>,
+                           limit: form::Result<'_, i64>, include_carriers: form::Result<'_, bool>) -> Result<Json<Vec<TradeRoute>>, ApiError> {
+    let odyssey = dlc?.odyssey();
+    let radius = check_trade_radius(query_param("radius", radius)?.unwrap_or(20.0))?;
+    let (limit, _) = page_bounds(query_param("limit", limit)?, None)?;
+    let limits = TradeLimits::new(cargo, query_param("min_profit", min_profit)?, query_param("min_stock", min_stock)?,
+                                  query_param("min_demand", min_demand)?, query_param("include_carriers", include_carriers)?)?;
+
+    let (start, stations) = {
+        let db = db.get().await?;
+        db.run(move |conn| {
+            let start = load_coordinates(conn, from, odyssey)?;
+            Ok::<_, ApiError>((start, load_trade_stations(conn, odyssey, start, radius, &limits)?))
+        }).await?
+    };
+    //Comparing the stations is plain computation, the connection is back in the pool by now
+    let routes = rocket::tokio::task::spawn_blocking(move || single_hop_routes(&stations, start, &limits, limit as usize))
+        .await
+        .map_err(|e| ApiError::Internal(e.to_string()))?;
+    Ok(Json(routes))
+}
+
+/// The `limit` most profitable routes between `stations`, `start` being the position of the `from` system.
+fn single_hop_routes(stations: &[TradeStation], start: Coordinates, limits: &TradeLimits, limit: usize) -> Vec<TradeRoute> {
+    let mut routes = vec![];
+    for ((source, destination), trades) in trades_between(stations, limits) {
+        let (source, destination) = (&stations[source], &stations[destination]);
+        routes.push(TradeRoute {
+            source: source.into(),
+            destination: destination.into(),
+            distance: source.position.distance(&destination.position),
+            distance_from_start: start.distance(&source.position),
+            trades,
+        });
+    }
+    routes.sort_by(|a, b| b.trades[0].total_profit.cmp(&a.trades[0].total_profit)
+        .then(b.trades[0].profit_per_ton.cmp(&a.trades[0].profit_per_ton))
+        .then(a.source.market_id.cmp(&b.source.market_id))
+        .then(a.destination.market_id.cmp(&b.destination.market_id)));
+    routes.truncate(limit);
+    routes
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TradeLeg {
+    pub source: StationRef,
+    pub destination: StationRef,
+    /// Light years between the two systems
+    pub distance: f64,
+    pub jumps: u32,
+    /// Nothing worth carrying on this leg if empty, which only happens on the way back to the start
+    pub trade: Option<Trade>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TradeLoop {
+    pub legs: Vec<TradeLeg>,
+    pub total_profit: i64,
+    /// Estimated seconds for a full round trip
+    pub duration: f64,
+    pub profit_per_hour: f64,
+}
+
+/// Loop routes starting and ending at a station in the `from` system, most profit per hour first.
+///
+/// Every station is visited at most once per loop. The search only follows the most profitable hops
+/// of each station, so results are deterministic for the same data but not guaranteed to be optimal.
+/// Like [`routes_single_hop`] only the nearest [`MAX_TRADE_STATIONS`] stations are considered.
+#[get("/<dlc>/trade/loops?<from>&<radius>&<jump_range>&<cargo>&<max_hops>&<min_profit>&<min_stock>&<min_demand>&<limit>&<include_carriers>")]
+#[allow(clippy::too_many_arguments)]
+async fn loops(db: LazyDbConn<'_>, dlc: Result<Dlc, ApiError>, from: i64, radius: form::Result<'_, f64>, jump_range: f64, cargo: i32,
+               max_hops: form::Result<'_, i64>, min_profit: form::Result<'_, i32>, min_stock: form::Result<'_, i32>, min_demand: form::Result<'_, i32>,
+               limit: form::Result<'_, i64>, include_carriers: form::Result<'_, bool>) -> Result<Json<Vec<TradeLoop>>, ApiError> {
+    let odyssey = dlc?.odyssey();
+    let radius = check_trade_radius(query_param("radius", radius)?.unwrap_or(20.0))?;
+    let (limit, _) = page_bounds(query_param("limit", limit)?, None)?;
+    let limits = TradeLimits::new(cargo, query_param("min_profit", min_profit)?, query_param("min_stock", min_stock)?,
+                                  query_param("min_demand", min_demand)?, query_param("include_carriers", include_carriers)?)?;
+    if jump_range <= 0.0 || !jump_range.is_finite() {
+        return Err(ApiError::BadRequest("Parameter jump_range must be greater than 0".to_string()));
+    }
+    //Signed so negative values reach the range check below instead of failing to parse
+    let max_hops = query_param("max_hops", max_hops)?.unwrap_or(3);
+    if !(2..=MAX_LOOP_HOPS as i64).contains(&max_hops) {
+        return Err(ApiError::BadRequest(format!("Parameter max_hops must be between 2 and {}", MAX_LOOP_HOPS)));
+    }
+    let max_hops = max_hops as usize;
+
+    let stations = {
+        let db = db.get().await?;
+        db.run(move |conn| {
+            let start = load_coordinates(conn, from, odyssey)?;
+            load_trade_stations(conn, odyssey, start, radius, &limits)
+        }).await?
+    };
+    let loops = rocket::tokio::task::spawn_blocking(move || plan_loops(&stations, from, &limits, jump_range, max_hops, limit as usize))
+        .await
+        .map_err(|e| ApiError::Internal(e.to_string()))??;
+    Ok(Json(loops))
+}
+
+/// The `limit` loops with the most profit per hour starting at a station of the system `from`.
+fn plan_loops(stations: &[TradeStation], from: i64, limits: &TradeLimits, jump_range: f64, max_hops: usize, limit: usize) -> Result<Vec<TradeLoop>, ApiError> {
+    let starts: Vec<usize> = (0..stations.len()).filter(|index| stations[*index].system_address == from).collect();
+    if starts.is_empty() {
+        return Err(ApiError::NotFound(format!("No station with a market in system {}", from)));
+    }
+
+    let planner = LoopPlanner::new(stations, limits, jump_range);
+    let mut loops = vec![];
+    for start in starts {
+        planner.search(&mut vec![start], &mut loops, max_hops);
+    }
+    loops.sort_by(|a, b| b.profit_per_hour.total_cmp(&a.profit_per_hour)
+        .then(b.total_profit.cmp(&a.total_profit))
+        .then_with(|| a.stops.cmp(&b.stops)));
+    loops.truncate(limit);
+    Ok(loops.into_iter().map(|candidate| planner.build(candidate)).collect())
+}
+
+/// Loop found by the search, stations given by their index.
+struct LoopCandidate {
+    stops: Vec<usize>,
+    total_profit: i64,
+    profit_per_hour: f64,
+}
+
+/// Depth first search for profitable loops over the best hops between stations.
+struct LoopPlanner<'a> {
+    stations: &'a [TradeStation],
+    jump_range: f64,
+    trades: HashMap<(usize, usize), Vec<Trade>>,
+    /// Most profitable destinations per station
+    hops: Vec<Vec<usize>>,
+}
+
+impl<'a> LoopPlanner<'a> {
+    fn new(stations: &'a [TradeStation], limits: &TradeLimits, jump_range: f64) -> Self {
+        let trades = trades_between(stations, limits);
+        let mut hops: Vec<Vec<(i64, usize)>> = vec![vec![]; stations.len()];
+        for ((source, destination), trades) in &trades {
+            hops[*source].push((trades[0].total_profit, *destination));
+        }
+        let hops = hops.into_iter().map(|mut destinations| {
+            destinations.sort_by(|a, b| b.0.cmp(&a.0).then(stations[a.1].market_id.cmp(&stations[b.1].market_id)));
+            destinations.into_iter().take(LOOP_BRANCHING).map(|(_, destination)| destination).collect()
+        }).collect();
+        LoopPlanner { stations, jump_range, trades, hops }
+    }
+
+    fn search(&self, stops: &mut Vec<usize>, loops: &mut Vec<LoopCandidate>, max_hops: usize) {
+        let current = stops[stops.len() - 1];
+        //Closing the loop from here makes it `stops.len()` hops long
+        if stops.len() >= 2 {
+            let (total_profit, duration) = self.totals(stops);
+            if total_profit > 0 {
+                loops.push(LoopCandidate {
+                    stops: stops.clone(),
+                    total_profit,
+                    profit_per_hour: total_profit as f64 / duration * 3600.0,
+                });
+            }
+        }
+        if stops.len() == max_hops {
+            return;
+        }
+        for next in &self.hops[current] {
+            if !stops.contains(next) {
+                stops.push(*next);
+                self.search(stops, loops, max_hops);
+                stops.pop();
+            }
+        }
+    }
+
+    /// Profit and seconds of the loop through `stops` and back to the first one.
+    fn totals(&self, stops: &[usize]) -> (i64, f64) {
+        let mut total_profit = 0;
+        let mut duration = 0.0;
+        for (source, destination) in Self::legs(stops) {
+            total_profit += self.best_trade(source, destination).map_or(0, |trade| trade.total_profit);
+            duration += self.duration(source, destination);
+        }
+        (total_profit, duration)
+    }
+
+    fn build(&self, candidate: LoopCandidate) -> TradeLoop {
+        let mut legs = vec![];
+        let mut duration = 0.0;
+        for (source, destination) in Self::legs(&candidate.stops) {
+            let (from, to) = (&self.stations[source], &self.stations[destination]);
+            duration += self.duration(source, destination);
+            legs.push(TradeLeg {
+                source: from.into(),
+                destination: to.into(),
+                distance: from.position.distance(&to.position),
+                jumps: self.jumps(source, destination),
+                trade: self.best_trade(source, destination).cloned(),
+            });
+        }
+        TradeLoop {
+            legs,
+            total_profit: candidate.total_profit,
+            duration,
+            profit_per_hour: candidate.profit_per_hour,
+        }
+    }
+
+    fn legs(stops: &[usize]) -> impl Iterator<Item=(usize, usize)> + '_ {
+        stops.iter().enumerate().map(|(index, source)| (*source, stops[(index + 1) % stops.len()]))
+    }
+
+    fn best_trade(&self, source: usize, destination: usize) -> Option<&Trade> {
+        self.trades.get(&(source, destination)).and_then(|trades| trades.first())
+    }
+
+    fn jumps(&self, source: usize, destination: usize) -> u32 {
+        let distance = self.stations[source].position.distance(&self.stations[destination].position);
+        (distance / self.jump_range).ceil() as u32
+    }
+
+    fn duration(&self, source: usize, destination: usize) -> f64 {
+        self.jumps(source, destination) as f64 * SECONDS_PER_JUMP + SECONDS_PER_STOP
+    }
+}
+
+impl TradeLimits {
+    pub fn new(cargo: i32, min_profit: Option<i32>, min_stock: Option<i32>, min_demand: Option<i32>, include_carriers: Option<bool>) -> Result<Self, ApiError> {
+        if cargo <= 0 {
+            return Err(ApiError::BadRequest("Parameter cargo must be greater than 0".to_string()));
+        }
+        Ok(TradeLimits {
+            cargo,
+            min_profit: min_profit.unwrap_or(1).max(1),
+            min_stock: min_stock.unwrap_or(DEFAULT_MIN_STOCK),
+            min_demand: min_demand.unwrap_or(DEFAULT_MIN_DEMAND),
+            include_carriers: include_carriers.unwrap_or(false),
+        })
+    }
+
+    /// Trade of one commodity, if buying it at `source` and selling it at `destination` is worth it.
+    pub fn trade(&self, source: &Offer, destination: &Offer) -> Option<Trade> {
+        let (profit_per_ton, quantity) = self.profit(source, destination)?;
+        Some(Trade {
+            commodity: source.commodity.clone(),
+            buy_price: source.buy_price,
+            sell_price: destination.sell_price,
+            profit_per_ton,
+            quantity,
+            total_profit: profit_per_ton as i64 * quantity as i64,
+        })
+    }
+
+    /// Profit per ton and tons of a [`TradeLimits::trade`], without building it.
+    fn profit(&self, source: &Offer, destination: &Offer) -> Option<(i32, i32)> {
+        let profit_per_ton = destination.sell_price - source.buy_price;
+        if profit_per_ton < self.min_profit {
+            return None;
+        }
+        Some((profit_per_ton, self.cargo.min(source.stock).min(destination.demand)))
+    }
+}
+
+pub fn check_trade_radius(radius: f64) -> Result<f64, ApiError> {
+    if !(radius > 0.0 && radius <= MAX_TRADE_RADIUS) {
+        return Err(ApiError::BadRequest(format!("Parameter radius must be greater than 0 and at most {}", MAX_TRADE_RADIUS)));
+    }
+    Ok(radius)
+}
+
+/// The nearest [`MAX_TRADE_STATIONS`] stations within `radius` light years of `center` buying or selling
+/// anything in useful amounts, with those commodities, ordered by market id.
+/// Fleet carriers are left out unless the limits include them.
+pub fn load_trade_stations(conn: &mut postgres::Client, odyssey: bool, center: Coordinates, radius: f64, limits: &TradeLimits) -> Result<Vec<TradeStation>, ApiError> {
+    let (distance, within_radius) = (distance_sql(2), within_radius_sql(2, 5));
+    //language=postgresql
+    let sql = format!("with nearby as (
+            select s.market_id,s.name,s.system_name,sy.address,sy.x,sy.y,sy.z,s.carrier
+            from system sy
+                inner join station s on s.system_name = sy.name
+            where sy.odyssey = $1
+              and {within_radius}
+              and ($8 or not s.carrier)
+              and exists (select 1 from commodity c where c.market_id = s.market_id and c.odyssey = sy.odyssey
+                  and ((c.buy_price > 0 and c.stock >= $6) or (c.sell_price > 0 and c.demand >= $7)))
+            order by {distance}, s.market_id
+            limit $9
+        )
+        select n.market_id,n.name,n.system_name,n.address,n.x,n.y,n.z,
+            c.name,coalesce(c.buy_price, 0),coalesce(c.sell_price, 0),coalesce(c.stock, 0),coalesce(c.demand, 0),
+            n.carrier
+        from nearby n
+            inner join commodity c on c.market_id = n.market_id and c.odyssey = $1
+        where (c.buy_price > 0 and c.stock >= $6) or (c.sell_price > 0 and c.demand >= $7)
+        order by n.market_id, c.name");
+    let rows = conn.query(sql.as_str(), &[&odyssey, &center.x, &center.y, &center.z, &radius, &limits.min_stock, &limits.min_demand,
+        &limits.include_carriers, &MAX_TRADE_STATIONS])?;
+
+    let mut stations: Vec<TradeStation> = vec![];
+    for row in rows {
+        let market_id: i64 = row.try_get(0)?;
+        if stations.last().map(|station| station.market_id) != Some(market_id) {
+            let Some(position) = Coordinates::from_columns(row.try_get(4)?, row.try_get(5)?, row.try_get(6)?) else {
+                continue;
+            };
+            stations.push(TradeStation {
+                market_id,
+                station: row.try_get(1)?,
+                system: row.try_get(2)?,
+                system_address: row.try_get(3)?,
+                carrier: row.try_get(12)?,
+                position,
+                offers: vec![],
+            });
+        }
+        if let Some(station) = stations.last_mut() {
+            station.offers.push(Offer {
+                commodity: row.try_get(7)?,
+                buy_price: row.try_get(8)?,
+                sell_price: row.try_get(9)?,
+                stock: row.try_get(10)?,
+                demand: row.try_get(11)?,
+            });
+        }
+    }
+    Ok(stations)
+}
+
+/// Profitable trades for every ordered pair of stations, keyed by their indices in `stations`.
+/// Each list holds the best [`COMMODITIES_PER_ROUTE`] trades, most total profit first.
+///
+/// Only the best offers of each pair are kept while going through all of them, so a [`Trade`]
+/// gets built for at most [`COMMODITIES_PER_ROUTE`] commodities per pair.
+pub fn trades_between(stations: &[TradeStation], limits: &TradeLimits) -> HashMap<(usize, usize), Vec<Trade>> {
+    let mut sellers: HashMap<&str, Vec<(usize, &Offer)>> = HashMap::new();
+    let mut buyers: HashMap<&str, Vec<(usize, &Offer)>> = HashMap::new();
+    for (index, station) in stations.iter().enumerate() {
+        for offer in &station.offers {
+            if offer.buy_price > 0 && offer.stock >= limits.min_stock {
+                sellers.entry(offer.commodity.as_str()).or_default().push((index, offer));
+            }
+            if offer.sell_price > 0 && offer.demand >= limits.min_demand {
+                buyers.entry(offer.commodity.as_str()).or_default().push((index, offer));
+            }
+        }
+    }
+
+    //Total profit, profit per ton and the two offers, best first
+    type Candidate<'a> = (i64, i32, &'a Offer, &'a Offer);
+    let ranks_before = |a: &Candidate, b: &Candidate| {
+        b.0.cmp(&a.0).then(b.1.cmp(&a.1)).then(a.2.commodity.cmp(&b.2.commodity)).is_lt()
+    };
+    let mut pairs: HashMap<(usize, usize), Vec<Candidate>> = HashMap::new();
+    for (commodity, sources) in &sellers {
+        let Some(destinations) = buyers.get(commodity) else {
+            continue;
+        };
+        for (source, source_offer) in sources {
+            for (destination, destination_offer) in destinations {
+                if source == destination {
+                    continue;
+                }
+                let Some((profit_per_ton, quantity)) = limits.profit(source_offer, destination_offer) else {
+                    continue;
+                };
+                let candidate = (profit_per_ton as i64 * quantity as i64, profit_per_ton, *source_offer, *destination_offer);
+                let best = pairs.entry((*source, *destination)).or_insert_with(|| Vec::with_capacity(COMMODITIES_PER_ROUTE + 1));
+                let position = best.iter().position(|kept| ranks_before(&candidate, kept)).unwrap_or(best.len());
+                if position < COMMODITIES_PER_ROUTE {
+                    best.insert(position, candidate);
+                    best.truncate(COMMODITIES_PER_ROUTE);
+                }
+            }
+        }
+    }
+
+    pairs.into_iter().map(|(pair, best)| {
+        let trades = best.into_iter().filter_map(|(_, _, source, destination)| limits.trade(source, destination)).collect();
+        (pair, trades)
+    }).collect()
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![routes_single_hop, loops]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Station of its own system on the x axis, every offer in plenty. Offers are (commodity, buy price, sell price).
+    fn station(market_id: i64, system_address: i64, x: f64, offers: &[(&str, i32, i32)]) -> TradeStation {
+        TradeStation {
+            market_id,
+            station: Some(format!("Station {}", market_id)),
+            system: Some(format!("System {}", system_address)),
+            system_address,
+            carrier: false,
+            position: Coordinates { x, y: 0.0, z: 0.0 },
+            offers: offers.iter().map(|(commodity, buy_price, sell_price)| Offer {
+                commodity: commodity.to_string(),
+                buy_price: *buy_price,
+                sell_price: *sell_price,
+                stock: if *buy_price > 0 { 10_000 } else { 0 },
+                demand: if *sell_price > 0 { 10_000 } else { 0 },
+            }).collect(),
+        }
+    }
+
+    fn limits() -> TradeLimits {
+        TradeLimits::new(100, None, None, None, None).unwrap()
+    }
+
+    /// Gold from 1 to 2 and silver back is the best round trip. 3 pays more for gold but is 4 jumps away.
+    fn stations() -> Vec<TradeStation> {
+        vec![
+            station(1, 1, 0.0, &[("gold", 100, 0), ("silver", 0, 600)]),
+            station(2, 2, 10.0, &[("gold", 0, 1100), ("silver", 100, 0), ("tea", 100, 0)]),
+            station(3, 3, 40.0, &[("gold", 0, 2000), ("silver", 100, 0), ("tea", 0, 300)]),
+        ]
+    }
+
+    fn stops(trade_loop: &TradeLoop) -> Vec<i64> {
+        trade_loop.legs.iter().map(|leg| leg.source.market_id).collect()
+    }
+
+    #[test]
+    fn trades_between_keeps_the_best_commodities_per_pair() {
+        let offers = [("gold", 100, 0), ("silver", 100, 0), ("tea", 100, 0), ("water", 100, 0), ("coffee", 100, 0)];
+        let demand = [("gold", 0, 600), ("silver", 0, 500), ("tea", 0, 900), ("water", 0, 200), ("coffee", 0, 900)];
+        let stations = vec![station(1, 1, 0.0, &offers), station(2, 2, 10.0, &demand)];
+
+        let trades = trades_between(&stations, &limits());
+        assert_eq!(trades.len(), 1);
+        let commodities: Vec<&str> = trades[&(0, 1)].iter().map(|trade| trade.commodity.as_str()).collect();
+        //Equal profits are ordered by name
+        assert_eq!(commodities, ["coffee", "tea", "gold"]);
+        assert_eq!(trades[&(0, 1)][0].total_profit, 80_000);
+    }
+
+    #[test]
+    fn trades_between_skips_unprofitable_and_too_small_offers() {
+        let mut destination = station(2, 2, 10.0, &[("gold", 0, 90), ("silver", 0, 500)]);
+        destination.offers[1].demand = 10;
+        let stations = vec![station(1, 1, 0.0, &[("gold", 100, 0), ("silver", 100, 0)]), destination];
+
+        assert!(trades_between(&stations, &limits()).is_empty());
+    }
+
+    #[test]
+    fn loop_with_the_most_profit_per_hour_comes_first() {
+        let loops = plan_loops(&stations(), 1, &limits(), 10.0, 3, 10).unwrap();
+
+        assert_eq!(stops(&loops[0]), [1, 2]);
+        assert_eq!(loops[0].total_profit, 150_000);
+        //Both legs take one jump and one stop
+        assert_eq!(loops[0].duration, 480.0);
+        assert!(loops.windows(2).all(|pair| pair[0].profit_per_hour >= pair[1].profit_per_hour));
+    }
+
+    #[test]
+    fn loops_are_not_longer_than_max_hops() {
+        let short = plan_loops(&stations(), 1, &limits(), 10.0, 2, 100).unwrap();
+        assert!(short.iter().all(|trade_loop| trade_loop.legs.len() == 2));
+
+        let long = plan_loops(&stations(), 1, &limits(), 10.0, 3, 100).unwrap();
+        assert!(long.iter().all(|trade_loop| trade_loop.legs.len() <= 3));
+        assert!(long.iter().any(|trade_loop| trade_loop.legs.len() == 3));
+    }
+
+    #[test]
+    fn loops_visit_every_station_once_and_return_to_the_start() {
+        let loops = plan_loops(&stations(), 1, &limits(), 10.0, 3, 100).unwrap();
+        assert!(!loops.is_empty());
+
+        for trade_loop in &loops {
+            let mut visited = stops(trade_loop);
+            assert_eq!(trade_loop.legs[0].source.system_address, 1);
+            assert_eq!(trade_loop.legs.last().unwrap().destination.market_id, visited[0]);
+            visited.sort();
+            visited.dedup();
+            assert_eq!(visited.len(), trade_loop.legs.len());
+        }
+    }
+
+    #[test]
+    fn loops_are_ordered_the_same_every_time() {
+        //4 is a copy of 2, so both round trips pay exactly the same
+        let mut stations = stations();
+        stations.push(station(4, 4, 10.0, &[("gold", 0, 1100), ("silver", 100, 0), ("tea", 100, 0)]));
+        let loops = plan_loops(&stations, 1, &limits(), 10.0, 2, 100).unwrap();
+
+        assert_eq!(stops(&loops[0]), [1, 2]);
+        assert_eq!(stops(&loops[1]), [1, 4]);
+        assert_eq!(loops[0].profit_per_hour, loops[1].profit_per_hour);
+        for _ in 0..5 {
+            let again = plan_loops(&stations, 1, &limits(), 10.0, 2, 100).unwrap();
+            assert_eq!(serde_json::to_value(&again).unwrap(), serde_json::to_value(&loops).unwrap());
+        }
+    }
+
+    #[test]
+    fn loops_need_a_station_in_the_start_system() {
+        let result = plan_loops(&stations(), 99, &limits(), 10.0, 3, 10);
+        assert!(matches!(result, Err(ApiError::NotFound(_))));
+    }
+}
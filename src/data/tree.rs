@@ -0,0 +1,242 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use rocket::{form, Route, State};
+use rocket::serde::{Deserialize, Serialize, json::Json};
+use serde_json::Value;
+
+use super::dlc::Dlc;
+use super::error::{query_param, ApiError};
+use super::parent::{Parent, ParentFormat};
+use super::{cached_system, Cache, LazyDbConn, Planet, Star, System};
+
+/// Node of the orbit hierarchy of a system.
+///
+/// Barycentres and rings are not stored as bodies, they only show up in the parents of other bodies
+/// and are synthesized from there. The same goes for stars and planets which are parents of scanned bodies
+/// but were never scanned themselves, they are returned as `unknown`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", tag = "kind", rename_all = "lowercase")]
+pub enum OrbitBody {
+    Star {
+        #[serde(flatten)]
+        star: Box<Star>,
+        children: Vec<OrbitBody>,
+    },
+    Planet {
+        #[serde(flatten)]
+        planet: Box<Planet>,
+        children: Vec<OrbitBody>,
+    },
+    Barycentre {
+        body_id: i32,
+        children: Vec<OrbitBody>,
+    },
+    Ring {
+        body_id: i32,
+        children: Vec<OrbitBody>,
+    },
+    Unknown {
+        body_id: i32,
+        children: Vec<OrbitBody>,
+    },
+}
+
+impl OrbitBody {
+    fn body_id(&self) -> i32 {
+        match self {
+            OrbitBody::Star { star, .. } => star.body_id.unwrap_or_default(),
+            OrbitBody::Planet { planet, .. } => planet.body_id.unwrap_or_default(),
+            OrbitBody::Barycentre { body_id, .. } | OrbitBody::Ring { body_id, .. } | OrbitBody::Unknown { body_id, .. } => *body_id,
+        }
+    }
+
+    fn semi_major_axis(&self) -> Option<f32> {
+        match self {
+            OrbitBody::Star { star, .. } => star.semi_major_axis,
+            OrbitBody::Planet { planet, .. } => planet.semi_major_axis,
+            _ => None,
+        }
+    }
+
+    fn children_mut(&mut self) -> &mut Vec<OrbitBody> {
+        match self {
+            OrbitBody::Star { children, .. } | OrbitBody::Planet { children, .. } | OrbitBody::Barycentre { children, .. }
+            | OrbitBody::Ring { children, .. } | OrbitBody::Unknown { children, .. } => children,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SystemTree {
+    pub name: Option<String>,
+    pub address: i64,
+    /// Bodies orbiting nothing, usually the main star or the barycentre of the whole system
+    pub bodies: Vec<OrbitBody>,
+}
+
+/// Stars and planets of a system nested by orbit.
+///
+/// Children are sorted by semi-major axis, innermost first. Nodes without a known orbit,
+/// which includes every synthesized barycentre and ring, come last. Ties are broken by body id.
+/// `parents=journal` returns the parents in the journal format.
+#[get("/<dlc>/system/<address>/tree?<parents>")]
+async fn tree(cache: &State<Arc<Cache>>, db: LazyDbConn<'_>, address: i64, dlc: Result<Dlc, ApiError>,
+              parents: form::Result<'_, ParentFormat>) -> Result<Json<Value>, ApiError> {
+    let odyssey = dlc?.odyssey();
+    let parents = query_param("parents", parents)?.unwrap_or_default();
+    let system = cached_system(cache, &db, odyssey, address).await?;
+    parents.apply(&build_tree(system, address)).map(Json)
+}
+
+fn build_tree(system: System, address: i64) -> SystemTree {
+    //Every node by body id and the body id of what it orbits. Scanned bodies come first,
+    //so the parent chain of another body never overwrites them or their orbit.
+    let mut nodes: BTreeMap<i32, OrbitBody> = BTreeMap::new();
+    let mut orbits: HashMap<i32, Option<i32>> = HashMap::new();
+    let mut chains: Vec<(i32, Vec<Parent>)> = vec![];
+
+    for star in system.stars.unwrap_or_default() {
+        let Some(body_id) = star.body_id else { continue };
+        chains.push((body_id, star.parents.clone()));
+        nodes.insert(body_id, OrbitBody::Star { star: Box::new(star), children: vec![] });
+    }
+    for planet in system.planets.unwrap_or_default() {
+        let Some(body_id) = planet.body_id else { continue };
+        //A planet sharing the body id of a star is left out together with its parents
+        if nodes.contains_key(&body_id) {
+            continue;
+        }
+        chains.push((body_id, planet.parents.clone()));
+        nodes.insert(body_id, OrbitBody::Planet { planet: Box::new(planet), children: vec![] });
+    }
+
+    for (body_id, parents) in &chains {
+        orbits.insert(*body_id, parents.first().map(|parent| parent.body_id()));
+    }
+    for (_, parents) in chains {
+        for (index, parent) in parents.iter().enumerate() {
+            let parent_id = parent.body_id();
+            orbits.entry(parent_id).or_insert_with(|| parents.get(index + 1).map(|parent| parent.body_id()));
+            nodes.entry(parent_id).or_insert_with(|| match parent {
+                Parent::Barycentre(body_id) => OrbitBody::Barycentre { body_id: *body_id, children: vec![] },
+                Parent::Ring(body_id) => OrbitBody::Ring { body_id: *body_id, children: vec![] },
+                Parent::Star(body_id) | Parent::Planet(body_id) => OrbitBody::Unknown { body_id: *body_id, children: vec![] },
+            });
+        }
+    }
+
+    let mut children: HashMap<Option<i32>, Vec<i32>> = HashMap::new();
+    for body_id in nodes.keys() {
+        let parent = orbits.get(body_id).copied().flatten().filter(|parent| nodes.contains_key(parent) && parent != body_id);
+        children.entry(parent).or_default().push(*body_id);
+    }
+
+    let roots = children.remove(&None).unwrap_or_default();
+    SystemTree {
+        name: system.name,
+        address,
+        bodies: attach(roots, &mut nodes, &mut children),
+    }
+}
+
+/// Takes the given nodes out of `nodes` and recursively fills in their children, sorted by orbit.
+/// Bodies caught in a cycle of parents are never reached from a root and get left out.
+fn attach(ids: Vec<i32>, nodes: &mut BTreeMap<i32, OrbitBody>, children: &mut HashMap<Option<i32>, Vec<i32>>) -> Vec<OrbitBody> {
+    let mut bodies = vec![];
+    for body_id in ids {
+        let Some(mut node) = nodes.remove(&body_id) else { continue };
+        let child_ids = children.remove(&Some(body_id)).unwrap_or_default();
+        *node.children_mut() = attach(child_ids, nodes, children);
+        bodies.push(node);
+    }
+    bodies.sort_by(|a, b| match (a.semi_major_axis(), b.semi_major_axis()) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    }.then(a.body_id().cmp(&b.body_id())));
+    bodies
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![tree]
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    /// System from journal like bodies, each given as `(body_id, semi-major axis, parents)`.
+    fn system(stars: &[(i32, Option<f32>, Vec<Parent>)], planets: &[(i32, Option<f32>, Vec<Parent>)]) -> System {
+        let bodies = |bodies: &[(i32, Option<f32>, Vec<Parent>)]| -> Vec<Value> {
+            bodies.iter().map(|(body_id, semi_major_axis, parents)| json!({
+                "body_id": body_id,
+                "semi_major_axis": semi_major_axis,
+                "parents": parents,
+            })).collect()
+        };
+        serde_json::from_value(json!({"stars": bodies(stars), "planets": bodies(planets)})).unwrap()
+    }
+
+    /// Nodes as `kind id(children)`, in order.
+    fn outline(bodies: &[OrbitBody]) -> String {
+        bodies.iter().map(|body| {
+            let kind = match body {
+                OrbitBody::Star { .. } => "star",
+                OrbitBody::Planet { .. } => "planet",
+                OrbitBody::Barycentre { .. } => "barycentre",
+                OrbitBody::Ring { .. } => "ring",
+                OrbitBody::Unknown { .. } => "unknown",
+            };
+            let children = match body {
+                OrbitBody::Star { children, .. } | OrbitBody::Planet { children, .. } | OrbitBody::Barycentre { children, .. }
+                | OrbitBody::Ring { children, .. } | OrbitBody::Unknown { children, .. } => children,
+            };
+            if children.is_empty() {
+                format!("{} {}", kind, body.body_id())
+            } else {
+                format!("{} {}({})", kind, body.body_id(), outline(children))
+            }
+        }).collect::<Vec<_>>().join(", ")
+    }
+
+    fn tree(system: System) -> String {
+        outline(&build_tree(system, 1).bodies)
+    }
+
+    #[test]
+    fn barycentres_are_synthesized() {
+        let system = system(&[(1, Some(2.0), vec![Parent::Barycentre(0)]), (2, Some(1.0), vec![Parent::Barycentre(0)])], &[]);
+        assert_eq!(tree(system), "barycentre 0(star 2, star 1)");
+    }
+
+    #[test]
+    fn rings_and_unscanned_parents_are_synthesized() {
+        let system = system(&[(1, None, vec![])], &[(5, None, vec![Parent::Ring(4), Parent::Planet(3), Parent::Star(1)])]);
+        assert_eq!(tree(system), "star 1(unknown 3(ring 4(planet 5)))");
+    }
+
+    #[test]
+    fn stars_win_over_planets_with_the_same_body_id() {
+        let system = system(&[(1, None, vec![])], &[(1, None, vec![Parent::Star(0)]), (2, None, vec![Parent::Star(1)])]);
+        assert_eq!(tree(system), "star 1(planet 2)");
+    }
+
+    #[test]
+    fn parent_cycles_are_left_out() {
+        let system = system(&[(1, None, vec![])], &[(7, None, vec![Parent::Planet(8)]), (8, None, vec![Parent::Planet(7)])]);
+        assert_eq!(tree(system), "star 1");
+    }
+
+    #[test]
+    fn children_are_sorted_by_orbit() {
+        let planets = [(2, Some(300.0), vec![Parent::Star(1)]), (3, Some(100.0), vec![Parent::Star(1)]), (4, None, vec![Parent::Star(1)]),
+                       (5, Some(200.0), vec![Parent::Star(1)]), (6, None, vec![Parent::Ring(9), Parent::Star(1)])];
+        let system = system(&[(1, None, vec![])], &planets);
+        assert_eq!(tree(system), "star 1(planet 3, planet 5, planet 2, planet 4, ring 9(planet 6))");
+    }
+}
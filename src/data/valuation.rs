@@ -0,0 +1,202 @@
+use std::sync::Arc;
+
+use rocket::{Route, State};
+use rocket::serde::{Deserialize, Serialize, json::Json};
+
+use super::body::BodyKind;
+use super::dlc::Dlc;
+use super::error::ApiError;
+use super::{cached_system, Cache, LazyDbConn};
+
+/// Mass scaling of planet values
+const PLANET_MASS_FACTOR: f64 = 0.56591828;
+/// Minimum a planet is worth, before the first discovery bonus
+const MIN_PLANET_VALUE: f64 = 500.0;
+/// Multiplier for being the first to discover a body
+const FIRST_DISCOVERY_BONUS: f64 = 2.6;
+/// Multiplier for mapping a planet with no more probes than needed
+const EFFICIENCY_BONUS: f64 = 1.25;
+/// Mapping multipliers depending on who discovered and mapped the planet first
+const FIRST_DISCOVERED_AND_MAPPED: f64 = 3.699622554;
+const FIRST_MAPPED: f64 = 8.0956;
+const MAPPED: f64 = 10.0 / 3.0;
+/// Odyssey adds 30% of the mapped value, but at least this much
+const MIN_ODYSSEY_MAPPING_BONUS: f64 = 555.0;
+
+/// Estimated payout of selling the cartographic data of a body, in credits.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ScanValue {
+    /// Value after a scan with the full spectrum scanner
+    pub fss: i64,
+    /// Value after also mapping the planet with the efficiency bonus, missing for stars which cannot be mapped
+    pub dss: Option<i64>,
+}
+
+/// Value of a star from its journal `StarType` and mass in solar masses.
+///
+/// Stars count as first discovered if `was_discovered` is false, unknown discovery state counts as discovered.
+pub fn star_value(star_type: Option<&str>, stellar_mass: Option<f32>, was_discovered: Option<bool>) -> Option<ScanValue> {
+    let k = match star_type? {
+        "N" | "H" => 22628.0,
+        "SupermassiveBlackHole" => 33.5678,
+        star_type if star_type.starts_with('D') => 14057.0,
+        _ => 1200.0,
+    };
+    let mut value = k + stellar_mass? as f64 * k / 66.25;
+    if !was_discovered.unwrap_or(true) {
+        value *= FIRST_DISCOVERY_BONUS;
+    }
+    Some(ScanValue { fss: value.round() as i64, dss: None })
+}
+
+/// Value of a planet from its journal `PlanetClass`, `TerraformState` and mass in earth masses.
+///
+/// Unknown discovery or mapping state counts as already discovered or mapped by someone else.
+pub fn planet_value(planet_class: Option<&str>, mass_em: Option<f32>, terraform_state: Option<&str>,
+                    was_discovered: Option<bool>, was_mapped: Option<bool>, odyssey: bool) -> Option<ScanValue> {
+    let terraformable = matches!(terraform_state, Some("Terraformable" | "Terraforming"));
+    let k = match planet_class? {
+        "Metal rich body" => 21790.0 + if terraformable { 65631.0 } else { 0.0 },
+        "Ammonia world" => 96932.0,
+        "Sudarsky class I gas giant" => 1656.0,
+        "Sudarsky class II gas giant" | "High metal content body" => 9654.0 + if terraformable { 100677.0 } else { 0.0 },
+        "Earthlike body" => 64831.0 + 116295.0,
+        "Water world" => 64831.0 + if terraformable { 116295.0 } else { 0.0 },
+        _ => 300.0 + if terraformable { 93328.0 } else { 0.0 },
+    };
+    let first_discovered = !was_discovered.unwrap_or(true);
+    let first_mapped = !was_mapped.unwrap_or(true);
+    let base = k + k * PLANET_MASS_FACTOR * (mass_em? as f64).powf(0.2);
+
+    let fss = base;
+    let multiplier = match (first_discovered, first_mapped) {
+        (true, true) => FIRST_DISCOVERED_AND_MAPPED,
+        (false, true) => FIRST_MAPPED,
+        _ => MAPPED,
+    };
+    let mut dss = base * multiplier;
+    if odyssey {
+        dss += (dss * 0.3).max(MIN_ODYSSEY_MAPPING_BONUS);
+    }
+    dss *= EFFICIENCY_BONUS;
+
+    let finish = |value: f64| {
+        let value = value.max(MIN_PLANET_VALUE) * if first_discovered { FIRST_DISCOVERY_BONUS } else { 1.0 };
+        value.round() as i64
+    };
+    Some(ScanValue { fss: finish(fss), dss: Some(finish(dss)) })
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BodyValue {
+    pub body_id: Option<i32>,
+    pub body_name: Option<String>,
+    pub kind: BodyKind,
+    pub value: ScanValue,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SystemValue {
+    pub name: Option<String>,
+    pub address: i64,
+    /// Sum over every known body after scanning them with the full spectrum scanner
+    pub fss: i64,
+    /// Same as `fss`, but with every planet mapped
+    pub dss: i64,
+    /// Known bodies with an estimated value, most valuable first. Bodies lacking class or mass are left out.
+    pub bodies: Vec<BodyValue>,
+}
+
+/// Estimated value of the cartographic data of every known body in the system.
+///
+/// System wide bonuses for scanning or mapping every body are not included.
+#[get("/<dlc>/system/<address>/value")]
+async fn system_value(cache: &State<Arc<Cache>>, db: LazyDbConn<'_>, address: i64, dlc: Result<Dlc, ApiError>) -> Result<Json<SystemValue>, ApiError> {
+    let odyssey = dlc?.odyssey();
+    let system = cached_system(cache, &db, odyssey, address).await?;
+
+    let stars = system.stars.iter().flatten().filter_map(|star| Some(BodyValue {
+        body_id: star.body_id,
+        body_name: star.body_name.clone(),
+        kind: BodyKind::Star,
+        value: star.estimated_value?,
+    }));
+    let planets = system.planets.iter().flatten().filter_map(|planet| Some(BodyValue {
+        body_id: planet.body_id,
+        body_name: planet.body_name.clone(),
+        kind: BodyKind::Planet,
+        value: planet.estimated_value?,
+    }));
+    let mut bodies: Vec<BodyValue> = stars.chain(planets).collect();
+    bodies.sort_by(|a, b| b.value.dss.unwrap_or(b.value.fss).cmp(&a.value.dss.unwrap_or(a.value.fss))
+        .then(a.body_id.cmp(&b.body_id)));
+
+    Ok(Json(SystemValue {
+        name: system.name,
+        address,
+        fss: bodies.iter().map(|body| body.value.fss).sum(),
+        dss: bodies.iter().map(|body| body.value.dss.unwrap_or(body.value.fss)).sum(),
+        bodies,
+    }))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![system_value]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn planet(class: &str, mass: f32, terraform_state: Option<&str>, first_discovered: bool, first_mapped: bool, odyssey: bool) -> ScanValue {
+        planet_value(Some(class), Some(mass), terraform_state, Some(!first_discovered), Some(!first_mapped), odyssey).unwrap()
+    }
+
+    #[test]
+    fn planet_classes() {
+        assert_eq!(planet("Earthlike body", 1.0, None, false, false, false), ScanValue { fss: 283629, dss: Some(1181785) });
+        assert_eq!(planet("Water world", 1.0, None, false, false, false), ScanValue { fss: 101520, dss: Some(423000) });
+        assert_eq!(planet("Water world", 1.0, Some("Terraformable"), false, false, false), ScanValue { fss: 283629, dss: Some(1181785) });
+        assert_eq!(planet("High metal content body", 2.0, None, false, false, false), ScanValue { fss: 15930, dss: Some(66374) });
+        assert_eq!(planet("High metal content body", 2.0, Some("Terraformable"), false, false, false), ScanValue { fss: 182054, dss: Some(758558) });
+    }
+
+    #[test]
+    fn mapping_multipliers() {
+        //First discovered and mapped, with the first discovery bonus on top
+        assert_eq!(planet("Earthlike body", 1.0, None, true, true, false), ScanValue { fss: 737434, dss: Some(3410285) });
+        assert_eq!(planet("Earthlike body", 1.0, None, false, true, false), ScanValue { fss: 283629, dss: Some(2870179) });
+        assert_eq!(planet("Earthlike body", 1.0, None, false, false, false).dss, Some(1181785));
+    }
+
+    #[test]
+    fn odyssey_mapping_bonus() {
+        assert_eq!(planet("Earthlike body", 1.0, None, false, false, true).dss, Some(1536321));
+        //30% of an icy body is less than the 555 credit floor
+        assert_eq!(planet("Icy body", 0.5, None, false, false, true).dss, Some(2560));
+        assert_eq!(planet("Icy body", 0.5, None, false, false, false).dss, Some(1866));
+    }
+
+    #[test]
+    fn minimum_planet_value() {
+        assert_eq!(planet("Icy body", 0.5, None, false, false, false).fss, 500);
+        assert_eq!(planet("Icy body", 0.5, None, true, false, false), ScanValue { fss: 1300, dss: Some(4851) });
+    }
+
+    #[test]
+    fn star_types() {
+        assert_eq!(star_value(Some("DA"), Some(0.5), Some(true)), Some(ScanValue { fss: 14163, dss: None }));
+        assert_eq!(star_value(Some("N"), Some(1.0), Some(true)), Some(ScanValue { fss: 22970, dss: None }));
+        assert_eq!(star_value(Some("SupermassiveBlackHole"), Some(4_000_000.0), Some(true)), Some(ScanValue { fss: 2026769, dss: None }));
+        assert_eq!(star_value(Some("K"), Some(0.5), Some(false)), Some(ScanValue { fss: 3144, dss: None }));
+    }
+
+    #[test]
+    fn unknown_class_or_mass_has_no_value() {
+        assert_eq!(star_value(None, Some(1.0), None), None);
+        assert_eq!(planet_value(Some("Icy body"), None, None, None, None, true), None);
+    }
+}